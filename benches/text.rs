@@ -0,0 +1,41 @@
+//! Throughput of text output, e.g. listing a BASIC program.
+//!
+//!     cargo bench --bench text
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use common::Host;
+
+fn listing() -> Vec<u8> {
+    let mut stream = Vec::new();
+    for line in 0..200 {
+        stream.extend_from_slice(format!("{:5} PRINT \"The quick brown fox jumps over the lazy dog\";X%\r\n", line * 10).as_bytes());
+    }
+    stream
+}
+
+fn coloured_text() -> Vec<u8> {
+    let mut stream = Vec::new();
+    for c in 0..2000 {
+        stream.extend_from_slice(&[17, (c % 15 + 1) as u8, 17, 128 + (c % 7) as u8]);
+        stream.extend_from_slice(b"Agon");
+    }
+    stream
+}
+
+fn bench_text(c: &mut Criterion) {
+    let mut host = Host::new();
+
+    let mut group = c.benchmark_group("text");
+    for (name, stream) in [("listing", listing()), ("coloured", coloured_text())] {
+        group.throughput(Throughput::Bytes(stream.len() as u64));
+        group.bench_function(name, |b| b.iter(|| host.send(&stream)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_text);
+criterion_main!(benches);
@@ -0,0 +1,78 @@
+//! Runs the VDP in its own window against an eZ80 host outside of this
+//! process, e.g.
+//!
+//!     cargo run --example standalone -- serial /dev/ttyUSB0 1152000
+//!     cargo run --example standalone -- tcp-listen 127.0.0.1:6502
+//!     cargo run --example standalone -- pty
+//!
+//! Fonts given with `--font ID:FILE` (PSF, BDF or raw 8 pixel wide
+//! glyphs) can be selected by the eZ80 with VDU 23,0,&95,0,ID;0.
+
+use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
+
+use agon_light_vdp::transport::{Link, Transport};
+use agon_light_vdp::VDP::{Font, VDP};
+use sdl2::event::Event;
+
+fn usage() -> ! {
+    eprintln!("Usage: standalone [--font ID:FILE]... tcp-connect HOST:PORT | tcp-listen ADDRESS:PORT | serial DEVICE [BAUD] | pty");
+    std::process::exit(1);
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mut fonts = Vec::new();
+    while args.first().map(|s| s.as_str()) == Some("--font") && args.len() > 1 {
+        let (id, path) = args[1].split_once(':').unwrap_or_else(|| usage());
+        let id: u16 = id.parse().unwrap_or_else(|_| usage());
+        let font = Font::load(std::path::Path::new(path)).unwrap_or_else(|e| {
+            eprintln!("Cannot load font: {}", e);
+            std::process::exit(1);
+        });
+        fonts.push((id, font));
+        args.drain(..2);
+    }
+    let transport = match args.iter().map(|s| s.as_str()).collect::<Vec<_>>()[..] {
+        ["tcp-connect", address] => Transport::TcpConnect(address.to_string()),
+        ["tcp-listen", address] => Transport::TcpListen(address.to_string()),
+        ["serial", device] => Transport::Serial(device.to_string(), 1152000),
+        ["serial", device, baud] => Transport::Serial(device.to_string(), baud.parse().unwrap_or_else(|_| usage())),
+        ["pty"] => Transport::Pty,
+        _ => usage(),
+    };
+    let link = Link::open(&transport).unwrap_or_else(|e| {
+        eprintln!("Cannot connect to host: {}", e);
+        std::process::exit(1);
+    });
+    println!("Connected to {}", link.name);
+
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let audio_subsystem = sdl_context.audio().unwrap();
+    let window = video_subsystem.window("Agon Light VDP", 512, 384)
+        .position_centered()
+        .build()
+        .unwrap();
+    let canvas = window.into_canvas().build().unwrap();
+    let texture_creator = canvas.texture_creator();
+    let vsync_counter = Arc::new(AtomicU32::new(0));
+    let mut vdp = VDP::new(canvas, &texture_creator, 1, link.tx, link.rx, vsync_counter, audio_subsystem).unwrap();
+    for (id, font) in fonts {
+        vdp.add_font(id, font);
+    }
+    vdp.start();
+
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    'running: loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit {..} => break 'running,
+                Event::KeyDown { scancode: Some(scancode), keymod, .. } => vdp.send_key(scancode, keymod, true),
+                Event::KeyUp { scancode: Some(scancode), keymod, .. } => vdp.send_key(scancode, keymod, false),
+                _ => {},
+            }
+        }
+        vdp.run();
+    }
+}
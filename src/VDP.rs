@@ -1,23 +1,48 @@
-use core::panic;
-use std::sync::mpsc::{Sender, Receiver, TryRecvError};
-use std::time::{Instant};
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::{Sender, Receiver};
+use std::time::{Duration, Instant};
 
 use sdl2::keyboard::{Mod, Scancode};
-use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::pixels::Color;
 use sdl2::rect::{Point, Rect};
-use sdl2::render::{Canvas, Texture, TextureCreator,BlendMode};
+use sdl2::render::{Canvas, TextureCreator};
 use sdl2::video::{Window, WindowContext};
+mod framebuffer;
+pub use framebuffer::Framebuffer;
+mod renderer;
+pub use renderer::{Renderer, HeadlessRenderer};
+mod sdl_renderer;
+pub use sdl_renderer::SdlRenderer;
 mod font;
 use font::font::FONT_BYTES;
 mod font_8x19;
 use font_8x19::font_8x19::FONT_8x19_BYTES;
+mod bitmap_font;
+pub use bitmap_font::Font;
+mod text_grid;
+use text_grid::{Cell, TextGrid};
+mod snapshot;
 use chrono::{Local,DateTime,Datelike,Timelike};
 mod audio;
-use audio::audio::AudioChannels;
+use audio::audio::{AudioChannels, ChannelState, FrequencyEnvelope, PcmSample, VolumeEnvelope, Waveform};
 use sdl2::AudioSubsystem;
 use log::{debug, info, warn, error};
 mod keymap;
 use self::keymap::*;
+mod vdu;
+mod graphics;
+use graphics::Span;
+
+// How the text cursor is drawn, chosen with VDU 23,1.
+#[derive(Clone, Copy, PartialEq)]
+enum CursorStyle {
+    // The scanlines set with VDU 23,0,&0A and &0B, full character width.
+    Block,
+    // The bottom of the character cell.
+    Underline,
+    // A thin line at the left of the character cell.
+    Bar,
+}
 
 struct Cursor {
     position_x: i32,
@@ -28,8 +53,24 @@ struct Cursor {
     font_height: i32,
     paged_mode: bool,
     paged_count: i32,
+    // Text window in pixels, right and bottom are exclusive.
+    window_left: i32,
+    window_top: i32,
+    window_right: i32,
+    window_bottom: i32,
+    // Behaviour flags set with VDU 23,16.
+    flags: u8,
+    // With scroll protection the move to the next line after printing in
+    // the last column waits until the next character is printed.
+    pending_wrap: bool,
 }
 
+// VDU 23,16 cursor flags.
+const SCROLL_PROTECT: u8 = 0x01;
+const RIGHT_TO_LEFT: u8 = 0x02;
+const BOTTOM_TO_TOP: u8 = 0x04;
+const NO_WRAP: u8 = 0x40;
+
 impl Cursor {
     fn new(screen_width: i32 , screen_height: i32, font_width: i32, font_height: i32) -> Cursor {
         Cursor {
@@ -41,44 +82,127 @@ impl Cursor {
             font_height,
             paged_mode: false,
             paged_count: 0,
+            window_left: 0,
+            window_top: 0,
+            window_right: screen_width,
+            window_bottom: screen_height,
+            flags: 0,
+            pending_wrap: false,
         }
     }
 
+    fn reset_window(&mut self) {
+        self.window_left = 0;
+        self.window_top = 0;
+        self.window_right = self.screen_width;
+        self.window_bottom = self.screen_height;
+    }
+
+    // Keep the cursor and text window on the same character cells after
+    // the font size changed from old_width x old_height.
+    fn rescale(&mut self, old_width: i32, old_height: i32) {
+        let columns = self.screen_width / self.font_width;
+        let rows = self.screen_height / self.font_height;
+        let x = |px: i32| (px / old_width).min(columns) * self.font_width;
+        let y = |px: i32| (px / old_height).min(rows) * self.font_height;
+        self.position_x = x(self.position_x);
+        self.position_y = y(self.position_y);
+        self.window_left = x(self.window_left);
+        self.window_top = y(self.window_top);
+        self.window_right = x(self.window_right);
+        self.window_bottom = y(self.window_bottom);
+        if self.window_right <= self.window_left || self.window_bottom <= self.window_top {
+            self.reset_window();
+        }
+        self.clamp_to_window();
+    }
+
+    fn window(&self) -> Rect {
+        Rect::new(self.window_left, self.window_top, (self.window_right - self.window_left) as u32, (self.window_bottom - self.window_top) as u32)
+    }
+
+    // Distance to the next character and line in the cursor direction.
+    fn step_x(&self) -> i32 {
+        if self.flags & RIGHT_TO_LEFT != 0 { -self.font_width } else { self.font_width }
+    }
+
+    fn step_y(&self) -> i32 {
+        if self.flags & BOTTOM_TO_TOP != 0 { -self.font_height } else { self.font_height }
+    }
+
+    fn clamp_to_window(&mut self) {
+        self.position_x = self.position_x.min(self.window_right - self.font_width).max(self.window_left);
+        self.position_y = self.position_y.min(self.window_bottom - self.font_height).max(self.window_top);
+    }
+
+    // Start of the line, the left or right edge of the text window.
     fn home(&mut self) {
-        self.position_x = 0;
+        self.pending_wrap = false;
+        self.position_x = if self.flags & RIGHT_TO_LEFT != 0 { self.window_right - self.font_width } else { self.window_left };
+    }
+
+    // Corner of the text window where printing starts, the top left one
+    // unless the cursor direction is changed.
+    fn window_home(&mut self) {
+        self.home();
+        self.position_y = if self.flags & BOTTOM_TO_TOP != 0 { self.window_bottom - self.font_height } else { self.window_top };
     }
 
     fn down(&mut self) {
-        self.position_y += self.font_height;
+        self.pending_wrap = false;
+        self.position_y += self.step_y();
         if self.paged_mode {
             self.paged_count += 1;
-            if self.paged_count * self.font_height >= self.screen_height {
+            if self.paged_count * self.font_height >= self.window_bottom - self.window_top {
                 self.paged_count = -2;
             }
         }
     }
 
     fn up(&mut self) {
-        self.position_y -= self.font_height;
-        if self.position_y < 0 {
-            self.position_y = 0;
-        }
+        self.pending_wrap = false;
+        self.position_y -= self.step_y();
+        self.clamp_to_window();
     }
 
     fn left(&mut self) {
-        self.position_x -= self.font_width;
-        if self.position_x < 0 {
-            self.position_x = 0;
-        }
+        self.pending_wrap = false;
+        self.position_x -= self.step_x();
+        self.clamp_to_window();
     }
 
     fn right(&mut self) {
-        self.position_x += self.font_width;
-        if self.position_x >= self.screen_width {
-            self.home();
-            self.down();
+        self.pending_wrap = false;
+        self.position_x += self.step_x();
+        if self.position_x < self.window_left || self.position_x + self.font_width > self.window_right {
+            if self.flags & NO_WRAP != 0 {
+                self.position_x -= self.step_x();
+            } else {
+                self.home();
+                self.down();
+            }
         }
     }
+
+    // Move on after printing a character. In the last column of a line
+    // scroll protection holds back the move to the next line.
+    fn advance(&mut self) {
+        let next = self.position_x + self.step_x();
+        if self.flags & (SCROLL_PROTECT | NO_WRAP) == SCROLL_PROTECT && (next < self.window_left || next + self.font_width > self.window_right) {
+            self.pending_wrap = true;
+        } else {
+            self.right();
+        }
+    }
+}
+
+// What a PLOT command does to the pixels it covers, selected by the two
+// lowest bits of the plot mode (0 only moves the graphics cursor).
+#[derive(Clone, Copy, PartialEq)]
+enum PlotAction {
+    Foreground,
+    Inverse,
+    Background,
 }
 
 struct Sprite
@@ -110,90 +234,207 @@ static COLOUR_LOOKUP: [sdl2::pixels::Color; 64] = [
 ];
 
 struct VideoMode{
+    number: u8,
     colors: u8,
     screen_width: u32,
     screen_height: u32,
     refresh_rate: u8,
+    double_buffered: bool,
     palette: &'static[&'static Color],
 }
 
 static PALETTE_2: [&'static Color; 2] = [&COLOUR_LOOKUP[0x00], &COLOUR_LOOKUP[0x3F]];
+static PALETTE_4: [&Color; 4] = [&COLOUR_LOOKUP[0x00], &COLOUR_LOOKUP[0x30], &COLOUR_LOOKUP[0x3C], &COLOUR_LOOKUP[0x3F]];
 static PALETTE_16: [&'static Color; 16] = [&COLOUR_LOOKUP[0x00], &COLOUR_LOOKUP[0x20], &COLOUR_LOOKUP[0x08], &COLOUR_LOOKUP[0x28], &COLOUR_LOOKUP[0x02], &COLOUR_LOOKUP[0x22], &COLOUR_LOOKUP[0x0A], &COLOUR_LOOKUP[0x2A], &COLOUR_LOOKUP[0x15], &COLOUR_LOOKUP[0x30], &COLOUR_LOOKUP[0x0C], &COLOUR_LOOKUP[0x3C], &COLOUR_LOOKUP[0x03], &COLOUR_LOOKUP[0x33], &COLOUR_LOOKUP[0x0F], &COLOUR_LOOKUP[0x3F]];
 static PALETTE_64: [&'static Color; 64] = [&COLOUR_LOOKUP[0x00], &COLOUR_LOOKUP[0x20], &COLOUR_LOOKUP[0x08], &COLOUR_LOOKUP[0x28], &COLOUR_LOOKUP[0x02], &COLOUR_LOOKUP[0x22], &COLOUR_LOOKUP[0x0A], &COLOUR_LOOKUP[0x2A], &COLOUR_LOOKUP[0x15], &COLOUR_LOOKUP[0x30], &COLOUR_LOOKUP[0x0C], &COLOUR_LOOKUP[0x3C], &COLOUR_LOOKUP[0x03], &COLOUR_LOOKUP[0x33], &COLOUR_LOOKUP[0x0F], &COLOUR_LOOKUP[0x3F], &COLOUR_LOOKUP[0x01], &COLOUR_LOOKUP[0x04], &COLOUR_LOOKUP[0x05], &COLOUR_LOOKUP[0x06], &COLOUR_LOOKUP[0x07], &COLOUR_LOOKUP[0x09], &COLOUR_LOOKUP[0x0B], &COLOUR_LOOKUP[0x0D], &COLOUR_LOOKUP[0x0E], &COLOUR_LOOKUP[0x10], &COLOUR_LOOKUP[0x11], &COLOUR_LOOKUP[0x12], &COLOUR_LOOKUP[0x13], &COLOUR_LOOKUP[0x14], &COLOUR_LOOKUP[0x16], &COLOUR_LOOKUP[0x17], &COLOUR_LOOKUP[0x18], &COLOUR_LOOKUP[0x19], &COLOUR_LOOKUP[0x1A], &COLOUR_LOOKUP[0x1B], &COLOUR_LOOKUP[0x1C], &COLOUR_LOOKUP[0x1D], &COLOUR_LOOKUP[0x1E], &COLOUR_LOOKUP[0x1F], &COLOUR_LOOKUP[0x21], &COLOUR_LOOKUP[0x23], &COLOUR_LOOKUP[0x24], &COLOUR_LOOKUP[0x25], &COLOUR_LOOKUP[0x26], &COLOUR_LOOKUP[0x27], &COLOUR_LOOKUP[0x29], &COLOUR_LOOKUP[0x2B], &COLOUR_LOOKUP[0x2C], &COLOUR_LOOKUP[0x2D], &COLOUR_LOOKUP[0x2E], &COLOUR_LOOKUP[0x2F], &COLOUR_LOOKUP[0x31], &COLOUR_LOOKUP[0x32], &COLOUR_LOOKUP[0x34], &COLOUR_LOOKUP[0x35], &COLOUR_LOOKUP[0x36], &COLOUR_LOOKUP[0x37], &COLOUR_LOOKUP[0x38], &COLOUR_LOOKUP[0x39], &COLOUR_LOOKUP[0x3A], &COLOUR_LOOKUP[0x3B], &COLOUR_LOOKUP[0x3D], &COLOUR_LOOKUP[0x3E]];
 
-static VIDEO_MODES: [VideoMode; 4] = [VideoMode{colors: 2, screen_width: 1024, screen_height: 768, refresh_rate: 60, palette: &PALETTE_2},
-                                    VideoMode{colors: 16, screen_width: 512, screen_height: 384, refresh_rate: 60, palette: &PALETTE_16},
-                                    VideoMode{colors: 64, screen_width: 320, screen_height: 200, refresh_rate: 75, palette: &PALETTE_64},
-                                    VideoMode{colors: 16, screen_width: 640, screen_height: 480, refresh_rate: 60, palette: &PALETTE_16}];
+// The Agon screen modes by MODE number. Modes from 128 up are double
+// buffered versions of mode number - 128.
+static VIDEO_MODES: [VideoMode; 38] = [
+    VideoMode{number: 0, colors: 16, screen_width: 640, screen_height: 480, refresh_rate: 60, double_buffered: false, palette: &PALETTE_16},
+    VideoMode{number: 1, colors: 4, screen_width: 640, screen_height: 480, refresh_rate: 60, double_buffered: false, palette: &PALETTE_4},
+    VideoMode{number: 2, colors: 2, screen_width: 640, screen_height: 480, refresh_rate: 60, double_buffered: false, palette: &PALETTE_2},
+    VideoMode{number: 3, colors: 64, screen_width: 640, screen_height: 240, refresh_rate: 60, double_buffered: false, palette: &PALETTE_64},
+    VideoMode{number: 4, colors: 16, screen_width: 640, screen_height: 240, refresh_rate: 60, double_buffered: false, palette: &PALETTE_16},
+    VideoMode{number: 5, colors: 4, screen_width: 640, screen_height: 240, refresh_rate: 60, double_buffered: false, palette: &PALETTE_4},
+    VideoMode{number: 6, colors: 2, screen_width: 640, screen_height: 240, refresh_rate: 60, double_buffered: false, palette: &PALETTE_2},
+    VideoMode{number: 7, colors: 16, screen_width: 640, screen_height: 480, refresh_rate: 60, double_buffered: false, palette: &PALETTE_16},  // Teletext, shown as a plain 16 colour mode.
+    VideoMode{number: 8, colors: 64, screen_width: 320, screen_height: 240, refresh_rate: 60, double_buffered: false, palette: &PALETTE_64},
+    VideoMode{number: 9, colors: 16, screen_width: 320, screen_height: 240, refresh_rate: 60, double_buffered: false, palette: &PALETTE_16},
+    VideoMode{number: 10, colors: 4, screen_width: 320, screen_height: 240, refresh_rate: 60, double_buffered: false, palette: &PALETTE_4},
+    VideoMode{number: 11, colors: 2, screen_width: 320, screen_height: 240, refresh_rate: 60, double_buffered: false, palette: &PALETTE_2},
+    VideoMode{number: 12, colors: 64, screen_width: 320, screen_height: 200, refresh_rate: 70, double_buffered: false, palette: &PALETTE_64},
+    VideoMode{number: 13, colors: 16, screen_width: 320, screen_height: 200, refresh_rate: 70, double_buffered: false, palette: &PALETTE_16},
+    VideoMode{number: 14, colors: 4, screen_width: 320, screen_height: 200, refresh_rate: 70, double_buffered: false, palette: &PALETTE_4},
+    VideoMode{number: 15, colors: 2, screen_width: 320, screen_height: 200, refresh_rate: 70, double_buffered: false, palette: &PALETTE_2},
+    VideoMode{number: 16, colors: 4, screen_width: 800, screen_height: 600, refresh_rate: 60, double_buffered: false, palette: &PALETTE_4},
+    VideoMode{number: 17, colors: 2, screen_width: 800, screen_height: 600, refresh_rate: 60, double_buffered: false, palette: &PALETTE_2},
+    VideoMode{number: 18, colors: 2, screen_width: 1024, screen_height: 768, refresh_rate: 60, double_buffered: false, palette: &PALETTE_2},
+    VideoMode{number: 19, colors: 4, screen_width: 1024, screen_height: 768, refresh_rate: 60, double_buffered: false, palette: &PALETTE_4},
+    VideoMode{number: 20, colors: 64, screen_width: 512, screen_height: 384, refresh_rate: 60, double_buffered: false, palette: &PALETTE_64},
+    VideoMode{number: 21, colors: 16, screen_width: 512, screen_height: 384, refresh_rate: 60, double_buffered: false, palette: &PALETTE_16},
+    VideoMode{number: 22, colors: 4, screen_width: 512, screen_height: 384, refresh_rate: 60, double_buffered: false, palette: &PALETTE_4},
+    VideoMode{number: 23, colors: 2, screen_width: 512, screen_height: 384, refresh_rate: 60, double_buffered: false, palette: &PALETTE_2},
+    VideoMode{number: 129, colors: 4, screen_width: 640, screen_height: 480, refresh_rate: 60, double_buffered: true, palette: &PALETTE_4},
+    VideoMode{number: 130, colors: 2, screen_width: 640, screen_height: 480, refresh_rate: 60, double_buffered: true, palette: &PALETTE_2},
+    VideoMode{number: 132, colors: 16, screen_width: 640, screen_height: 240, refresh_rate: 60, double_buffered: true, palette: &PALETTE_16},
+    VideoMode{number: 133, colors: 4, screen_width: 640, screen_height: 240, refresh_rate: 60, double_buffered: true, palette: &PALETTE_4},
+    VideoMode{number: 134, colors: 2, screen_width: 640, screen_height: 240, refresh_rate: 60, double_buffered: true, palette: &PALETTE_2},
+    VideoMode{number: 136, colors: 64, screen_width: 320, screen_height: 240, refresh_rate: 60, double_buffered: true, palette: &PALETTE_64},
+    VideoMode{number: 137, colors: 16, screen_width: 320, screen_height: 240, refresh_rate: 60, double_buffered: true, palette: &PALETTE_16},
+    VideoMode{number: 138, colors: 4, screen_width: 320, screen_height: 240, refresh_rate: 60, double_buffered: true, palette: &PALETTE_4},
+    VideoMode{number: 139, colors: 2, screen_width: 320, screen_height: 240, refresh_rate: 60, double_buffered: true, palette: &PALETTE_2},
+    VideoMode{number: 140, colors: 64, screen_width: 320, screen_height: 200, refresh_rate: 70, double_buffered: true, palette: &PALETTE_64},
+    VideoMode{number: 141, colors: 16, screen_width: 320, screen_height: 200, refresh_rate: 70, double_buffered: true, palette: &PALETTE_16},
+    VideoMode{number: 142, colors: 4, screen_width: 320, screen_height: 200, refresh_rate: 70, double_buffered: true, palette: &PALETTE_4},
+    VideoMode{number: 143, colors: 2, screen_width: 320, screen_height: 200, refresh_rate: 70, double_buffered: true, palette: &PALETTE_2},
+    VideoMode{number: 145, colors: 2, screen_width: 800, screen_height: 600, refresh_rate: 60, double_buffered: true, palette: &PALETTE_2},
+];
+
+fn video_mode(number: u8) -> Option<&'static VideoMode> {
+    VIDEO_MODES.iter().find(|m| m.number == number)
+}
+
+// Time between blinks of the text cursor.
+const CURSOR_BLINK_FAST: Duration = Duration::from_millis(250);
+const CURSOR_BLINK_SLOW: Duration = Duration::from_millis(500);
+
+// Font ids of the built in fonts.
+const SYSTEM_FONT: u16 = 65535;
+const TERMINAL_FONT: u16 = 65534;
 
 pub struct VDP<'a> {
     cursor: Cursor,
-    canvas: Canvas<Window>,
-    texture: Texture<'a>,
-    texture_creator: &'a TextureCreator<WindowContext>,
+    renderer: Box<dyn Renderer + 'a>,
+    // Palette indices, turned into RGB by compose_frame. In double
+    // buffered modes `screen` is the back buffer everything is drawn to
+    // and `front` the one on show.
+    screen: Framebuffer<u8>,
+    front: Framebuffer<u8>,
+    flip_pending: bool,
+    display: Framebuffer,
     tx: Sender<u8>,
     rx: Receiver<u8>,
-    foreground_color: sdl2::pixels::Color,
-    background_color: sdl2::pixels::Color,
-    graph_color: sdl2::pixels::Color,
+    input: VecDeque<u8>,
+    foreground_color: u8,
+    background_color: u8,
+    graph_color: u8,
+    graph_bg_color: u8,
+    graph_mode: u8,
+    graph_bg_mode: u8,
     cursor_active: bool,
     cursor_enabled: bool,
     cursor_last_change: Instant,
+    cursor_style: CursorStyle,
+    // First and last scanline of a block cursor, the last one is
+    // limited to the font height.
+    cursor_start: u8,
+    cursor_end: u8,
+    // Time between cursor blinks, None for a steady cursor.
+    cursor_blink: Option<Duration>,
     vsync_counter: std::sync::Arc<std::sync::atomic::AtomicU32>,
     last_vsync: Instant,
     current_video_mode: &'static VideoMode,
+    // Logical colours of the current mode, changed by VDU 19.
+    palette: Vec<Color>,
     logical_coords: bool,
     terminal_mode: bool,
+    text_at_graphics_cursor: bool,
     terminal_underline: bool,
     terminal_reverse: bool,
+    graph_cursor: Point,
     p1: Point,
     p2: Point,
     p3: Point,
     graph_origin: Point,
-    font_data: Vec<u8>,
+    graph_window: Rect,
+    font: Font,
+    // Id of `font` in `fonts`, redefined characters are kept there too.
+    font_id: u16,
+    // Loaded fonts by buffer id, next to the built in SYSTEM_FONT and
+    // TERMINAL_FONT.
+    fonts: HashMap<u16, Font>,
+    buffers: HashMap<u16, Vec<u8>>,
+    // Rendered characters by (character, foreground, background, underline),
+    // must be cleared whenever the font changes.
+    glyph_cache: HashMap<(u8, u8, u8, bool), Framebuffer<u8>>,
+    // The characters printed on the screen, for VDU 23,0,&83 and for
+    // redrawing the text in another font.
+    text: TextGrid,
     audio_channels: AudioChannels,
     num_sprites: u8,
     num_sprites_shown: u8,
     current_sprite: u8,
     current_bitmap: u8,
-    bitmaps: Vec<Option<Texture<'a>>>,
+    bitmaps: Vec<Option<Framebuffer>>,
     sprites: Vec<Sprite>,
-    scale_window: u8,
     keyboard_layout: Box<dyn KeyboardLayout>,
 }
 
-impl VDP<'_> {
-    pub fn new(canvas: Canvas<Window>, texture_creator: &TextureCreator<WindowContext>, scale_window: u8, tx: Sender<u8>, rx: Receiver<u8>, vsync_counter: std::sync::Arc<std::sync::atomic::AtomicU32>, audio_subsystem: AudioSubsystem) -> Result<VDP, String> {
-        let mode =  &VIDEO_MODES[1];
-    
-        let texture = texture_creator.create_texture(None, sdl2::render::TextureAccess::Target, mode.screen_width, mode.screen_height).unwrap();
+impl<'a> VDP<'a> {
+    pub fn new(canvas: Canvas<Window>, texture_creator: &'a TextureCreator<WindowContext>, scale_window: u8, tx: Sender<u8>, rx: Receiver<u8>, vsync_counter: std::sync::Arc<std::sync::atomic::AtomicU32>, audio_subsystem: AudioSubsystem) -> Result<VDP<'a>, String> {
+        let renderer = SdlRenderer::new(canvas, texture_creator, scale_window);
+        Ok(Self::with_renderer(Box::new(renderer), tx, rx, vsync_counter, Some(audio_subsystem)))
+    }
+
+    /// Create a VDP without window and audio device, e.g. for running
+    /// VDU streams in CI. Use `frame` to look at the result.
+    pub fn headless(tx: Sender<u8>, rx: Receiver<u8>, vsync_counter: std::sync::Arc<std::sync::atomic::AtomicU32>) -> VDP<'a> {
+        Self::with_renderer(Box::new(HeadlessRenderer::new()), tx, rx, vsync_counter, None)
+    }
 
-        Ok({
+    pub fn with_renderer(renderer: Box<dyn Renderer + 'a>, tx: Sender<u8>, rx: Receiver<u8>, vsync_counter: std::sync::Arc<std::sync::atomic::AtomicU32>, audio_subsystem: Option<AudioSubsystem>) -> VDP<'a> {
+        let mode =  &VIDEO_MODES[0];
+        let screen = Framebuffer::new(mode.screen_width, mode.screen_height, 0);
+
+        {
             let mut v=VDP {
             cursor: Cursor::new(mode.screen_width as i32, mode.screen_height as i32, 8, 8),
-            canvas,
-            texture,
-            texture_creator,
+            renderer,
+            display: Framebuffer::new(mode.screen_width, mode.screen_height, Color::RGB(0, 0, 0)),
+            front: screen.clone(),
+            flip_pending: false,
+            screen,
             tx,
             rx,
-            foreground_color: Color::RGB(255, 255, 255),
-            background_color: Color::RGB(0, 0, 0),
-            graph_color: Color::RGB(255, 255, 255),
+            input: VecDeque::new(),
+            foreground_color: 15,
+            background_color: 0,
+            graph_color: 15,
+            graph_bg_color: 0,
+            graph_mode: 0,
+            graph_bg_mode: 0,
             cursor_active: false,
             cursor_enabled: true,
             cursor_last_change: Instant::now(),
+            cursor_style: CursorStyle::Block,
+            cursor_start: 0,
+            cursor_end: 255,
+            cursor_blink: Some(CURSOR_BLINK_SLOW),
             vsync_counter,
             last_vsync: Instant::now(),
             current_video_mode: mode,
-            font_data: FONT_BYTES.to_vec(),
+            palette: mode.palette.iter().map(|&c| *c).collect(),
+            font: Font::from_raw(8, 8, 32, &FONT_BYTES).unwrap(),
+            font_id: SYSTEM_FONT,
+            fonts: HashMap::from([
+                (SYSTEM_FONT, Font::from_raw(8, 8, 32, &FONT_BYTES).unwrap()),
+                (TERMINAL_FONT, Font::from_raw(8, 19, 0, &FONT_8x19_BYTES).unwrap()),
+            ]),
+            buffers: HashMap::new(),
+            glyph_cache: HashMap::new(),
+            text: TextGrid::new(mode.screen_width as i32 / 8, mode.screen_height as i32 / 8),
             logical_coords: true,
             terminal_mode: false,
+            text_at_graphics_cursor: false,
             terminal_reverse: false,
             terminal_underline: false,
+            graph_cursor: Point::new(0,0),
             p1: Point::new(0,0),
             p2: Point::new(0,0),
             p3: Point::new(0,0),
             graph_origin: Point::new(0,0),
+            graph_window: Rect::new(0, 0, mode.screen_width, mode.screen_height),
             audio_channels: AudioChannels::new(audio_subsystem),
             num_sprites: 0,
             num_sprites_shown: 0,
@@ -201,7 +442,6 @@ impl VDP<'_> {
             current_bitmap: 0,
             bitmaps: Vec::new(),
             sprites: Vec::new(),
-            scale_window,
             keyboard_layout: Box::new(KeyboardLayoutUS{}),
             };
             for _ in 0..256 {
@@ -213,11 +453,13 @@ impl VDP<'_> {
             }
             v
         }
-        )        
     }
-    
+
+}
+
+impl VDP<'_> {
     pub fn start(&mut self) {
-        self.change_mode(1);
+        self.change_mode(&VIDEO_MODES[0]);
         self.bootscreen();
     }
     
@@ -227,19 +469,37 @@ impl VDP<'_> {
         }
         
         if self.last_vsync.elapsed().as_micros() >  (1_000_000u32 / self.current_video_mode.refresh_rate as u32).into() {
-            self.vsync_counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
             self.last_vsync = Instant::now();
-    
-    
-            let result = self.canvas.copy(&self.texture, None, None);
-            if result.is_err() {
-                panic!("Fail!");
+            self.vsync();
+        }
+    }
+
+    /// Vertical blank: swap the buffers if a flip was requested and show
+    /// the next frame. `run` calls this at the refresh rate of the mode.
+    pub fn vsync(&mut self) {
+        self.vsync_counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        if self.flip_pending {
+            if self.current_video_mode.double_buffered {
+                std::mem::swap(&mut self.screen, &mut self.front);
             }
-            self.canvas.set_blend_mode(BlendMode::Blend);
-            self.blink_cursor();
-            self.show_sprites();
-            self.canvas.present();
+            self.flip_pending = false;
         }
+        self.blink_cursor();
+        self.compose_frame();
+        self.renderer.present(&self.display);
+    }
+
+    /// True while a buffer flip holds up the command stream until the
+    /// next `vsync`.
+    pub fn waiting_for_vsync(&self) -> bool {
+        self.flip_pending
+    }
+
+    /// The current screen content as it would be shown on the display,
+    /// including sprites and the text cursor.
+    pub fn frame(&mut self) -> &Framebuffer {
+        self.compose_frame();
+        &self.display
     }
 
     pub fn send_key(&mut self, scancode: Scancode, keymod: Mod, down: bool) {
@@ -283,13 +543,26 @@ impl VDP<'_> {
 }
 
 impl VDP<'_> {
-    fn change_mode(&mut self, mode: usize) {
-        self.current_video_mode = &VIDEO_MODES[mode];
+    fn change_mode(&mut self, mode: &'static VideoMode) {
+        info!("Mode {}: {}x{} {} colours{}", mode.number, mode.screen_width, mode.screen_height, mode.colors,
+              if mode.double_buffered { ", double buffered" } else { "" });
+        self.current_video_mode = mode;
+        self.palette = self.current_video_mode.palette.iter().map(|&c| *c).collect();
+        self.foreground_color = (self.palette.len() - 1) as u8;
+        self.background_color = 0;
+        self.graph_color = self.foreground_color;
+        self.graph_bg_color = 0;
         self.cursor.screen_height = self.current_video_mode.screen_height as i32;
         self.cursor.screen_width = self.current_video_mode.screen_width as i32;
-        self.canvas.window_mut().set_size(self.current_video_mode.screen_width * self.scale_window as u32, self.current_video_mode.screen_height * self.scale_window as u32).unwrap();
-        self.texture = self.texture_creator.create_texture(None, sdl2::render::TextureAccess::Target, self.current_video_mode.screen_width, self.current_video_mode.screen_height).unwrap();
+        self.cursor.reset_window();
+        self.graph_window = Rect::new(0, 0, self.current_video_mode.screen_width, self.current_video_mode.screen_height);
+        self.renderer.set_mode(self.current_video_mode.screen_width, self.current_video_mode.screen_height);
+        self.screen = Framebuffer::new(self.current_video_mode.screen_width, self.current_video_mode.screen_height, self.background_color);
+        self.front = self.screen.clone();
+        self.flip_pending = false;
+        self.text = TextGrid::new(self.cursor.screen_width / self.cursor.font_width, self.cursor.screen_height / self.cursor.font_height);
         self.cls();
+        self.graph_cursor = Point::new(0, 0);
         self.p1.x = 0;
         self.p1.y = 0;
         self.p2.x = 0;
@@ -300,48 +573,184 @@ impl VDP<'_> {
         self.graph_origin.y = 0;
     }
     
-    fn get_points_from_font(bytes : Vec<u8>, underline: bool) -> Vec<Point>
-    {
-        let mut points: Vec<Point> = Vec::new();
-        let mut y = 0;
-        for byte in bytes.iter()
-        {
-            let b1 = if underline && y==(bytes.len() as i32 - 1) {0xffu8} else {*byte};
-            for bit in 0..8
-            {
-                if b1 & (1 << bit) != 0
-                {
-                    points.push(Point::new(7 - bit, y));
+    // Draw a character of the current font into a bitmap of its own.
+    fn render_glyph(&self, ascii: u8, fg: u8, bg: u8, underline: bool) -> Framebuffer<u8> {
+        let (w, h) = (self.font.width() as i32, self.font.height() as i32);
+        let mut glyph = Framebuffer::new(w as u32, h as u32, bg);
+        for y in 0..h {
+            for x in 0..w {
+                if self.font.pixel(ascii, x, y) || (underline && y == h - 1) {
+                    glyph.set_pixel(x, y, fg);
                 }
             }
-            y = y + 1;
         }
-        points
+        glyph
     }
-    
+
+    /// Make a font available to the font selection command, e.g. one
+    /// loaded from disk with `Font::load`.
+    pub fn add_font(&mut self, id: u16, font: Font) {
+        self.fonts.insert(id, font);
+    }
+
+    // Switch to another font, the text on the screen is redrawn in it.
+    fn select_font(&mut self, id: u16) {
+        let Some(font) = self.fonts.get(&id) else {
+            warn!("No font {}", id);
+            return;
+        };
+        let (old_width, old_height) = (self.cursor.font_width, self.cursor.font_height);
+        self.font = font.clone();
+        self.font_id = id;
+        self.cursor.font_width = font.width() as i32;
+        self.cursor.font_height = font.height() as i32;
+        self.cursor.rescale(old_width, old_height);
+        self.glyph_cache.clear();
+
+        for row in 0..self.text.rows() {
+            for column in 0..self.text.columns() {
+                if let Some(cell) = self.text.get(column, row) {
+                    self.screen.fill_rect(Rect::new(column * old_width, row * old_height, old_width as u32, old_height as u32), cell.bg);
+                }
+            }
+        }
+        self.text = self.text.resized(self.cursor.screen_width / self.cursor.font_width, self.cursor.screen_height / self.cursor.font_height);
+        for row in 0..self.text.rows() {
+            for column in 0..self.text.columns() {
+                if let Some(cell) = self.text.get(column, row) {
+                    self.draw_cell(column * self.cursor.font_width, row * self.cursor.font_height, cell);
+                }
+            }
+        }
+    }
+
+    // The character cells lying completely inside of `area`, if any.
+    fn cells_in(&self, area: Rect) -> Option<Rect> {
+        let (w, h) = (self.cursor.font_width, self.cursor.font_height);
+        let left = (area.x() + w - 1).div_euclid(w);
+        let top = (area.y() + h - 1).div_euclid(h);
+        let right = area.right().div_euclid(w);
+        let bottom = area.bottom().div_euclid(h);
+        if left < right && top < bottom {
+            Some(Rect::new(left, top, (right - left) as u32, (bottom - top) as u32))
+        } else {
+            None
+        }
+    }
+
+    // The character cells `area` overlaps with, if any.
+    fn cells_touched(&self, area: Rect) -> Option<Rect> {
+        let (w, h) = (self.cursor.font_width, self.cursor.font_height);
+        let left = area.x().div_euclid(w).max(0);
+        let top = area.y().div_euclid(h).max(0);
+        let right = (area.right() + w - 1).div_euclid(w).min(self.text.columns());
+        let bottom = (area.bottom() + h - 1).div_euclid(h).min(self.text.rows());
+        if left < right && top < bottom {
+            Some(Rect::new(left, top, (right - left) as u32, (bottom - top) as u32))
+        } else {
+            None
+        }
+    }
+
+    // Forget the characters under `area` after drawing over it with
+    // anything but text.
+    fn text_drawn_over(&mut self, area: Rect) {
+        if let Some(cells) = self.cells_touched(area) {
+            self.text.clear(cells);
+        }
+    }
+
+    // Print a character at the text cursor and move the cursor on.
+    fn print_char(&mut self, ascii: u8) {
+        if self.cursor.pending_wrap {
+            self.cursor.home();
+            self.cursor.down();
+            self.check_scrolling_needed();
+        }
+        self.render_char(ascii);
+        self.cursor.advance();
+        self.check_scrolling_needed();
+    }
+
     fn render_char(&mut self, ascii: u8)
     {
         //info!("Render {:#02X?}", ascii);
         if ascii >= 32 {
-            let shifted_ascii = ascii - 32;
-            let start = (self.cursor.font_height * (shifted_ascii as i32)) as usize;
-            let end = start+self.cursor.font_height as usize;
-            let mut points = Self::get_points_from_font(self.font_data[start..end].to_vec(),self.terminal_underline);
-            
-            for point in points.iter_mut() {
-                point.x += self.cursor.position_x;
-                point.y += self.cursor.position_y;
-            }
+            let (fg, bg) = if self.terminal_reverse {
+                (self.background_color, self.foreground_color)
+            } else {
+                (self.foreground_color, self.background_color)
+            };
+            let cell = Cell { c: ascii, fg, bg, underline: self.terminal_underline };
+            let (x, y) = (self.cursor.position_x, self.cursor.position_y);
+            self.text.set(x / self.cursor.font_width, y / self.cursor.font_height, Some(cell));
+            self.draw_cell(x, y, cell);
+        }
+    }
+
+    fn draw_cell(&mut self, x: i32, y: i32, cell: Cell) {
+        let key = (cell.c, cell.fg, cell.bg, cell.underline);
+        if !self.glyph_cache.contains_key(&key) {
+            let glyph = self.render_glyph(cell.c, cell.fg, cell.bg, cell.underline);
+            self.glyph_cache.insert(key, glyph);
+        }
+        let glyph = &self.glyph_cache[&key];
+        let size = Rect::new(0, 0, glyph.width(), glyph.height());
+        self.screen.copy_from(glyph, size, Rect::new(x, y, size.width(), size.height()));
+    }
+
+    // Size of a character in graphics coordinates, the height is negative
+    // when y grows upwards.
+    fn graphics_char_size(&self) -> (i32, i32) {
+        if self.logical_coords {
+            let up = |n: i32, d: i32| (n + d - 1) / d;
+            (up(self.cursor.font_width * 1280, self.cursor.screen_width), -up(self.cursor.font_height * 1024, self.cursor.screen_height))
+        } else {
+            (self.cursor.font_width, self.cursor.font_height)
+        }
+    }
 
-            self.canvas.with_texture_canvas(&mut self.texture, |texture_canvas| {
-                texture_canvas.set_draw_color(if self.terminal_reverse {self.foreground_color} else {self.background_color});
-                texture_canvas.fill_rect(Rect::new(self.cursor.position_x, self.cursor.position_y, 8, self.cursor.font_height as u32)).unwrap();
-                texture_canvas.set_draw_color(if self.terminal_reverse {self.background_color} else {self.foreground_color});
-                texture_canvas.draw_points(&points[..]).unwrap();
-            }).unwrap();
+    // VDU 5: characters are drawn at the graphics cursor in the graphics
+    // colour and GCOL mode without background, and the cursor movement
+    // codes move the graphics cursor.
+    fn print_graphics(&mut self, n: u8) {
+        let (w, h) = self.graphics_char_size();
+        match n {
+            0x08 => self.graph_cursor.x -= w,
+            0x09 => self.graph_cursor.x += w,
+            0x0A => self.graph_cursor.y += h,
+            0x0B => self.graph_cursor.y -= h,
+            0x0D => {
+                let left = self.graph_window.x() - self.graph_origin.x;
+                self.graph_cursor.x = if self.logical_coords {
+                    (left * 1280 + self.cursor.screen_width - 1) / self.cursor.screen_width
+                } else {
+                    left
+                };
+            },
+            0x7F => {
+                self.graph_cursor.x -= w;
+                let p = self.translate(self.scale(self.graph_cursor));
+                let size = Point::new(self.cursor.font_width - 1, self.cursor.font_height - 1);
+                self.plot_spans(&graphics::rectangle_spans(p, p + size), PlotAction::Background);
+            },
+            _ => self.draw_graphics_char(n),
         }
     }
 
+    fn draw_graphics_char(&mut self, c: u8) {
+        info!("Graphics character: {}", c as char);
+        let p = self.translate(self.scale(self.graph_cursor));
+        for row in 0..self.cursor.font_height {
+            for col in 0..self.cursor.font_width {
+                if self.font.pixel(c, col, row) {
+                    self.plot_pixel(p.x + col, p.y + row, PlotAction::Foreground);
+                }
+            }
+        }
+        self.graph_cursor.x += self.graphics_char_size().0;
+    }
+
     fn bootscreen(&mut self) {
         let boot_message = "Agon Quark VDP Version 1.03";
         for byte in boot_message.as_bytes() {
@@ -354,20 +763,74 @@ impl VDP<'_> {
 
     
     fn blink_cursor(&mut self) {
-        if self.cursor_last_change.elapsed().as_millis() > 500 {
-            self.cursor_active = !self.cursor_active;
-            self.cursor_last_change = Instant::now();
+        match self.cursor_blink {
+            Some(rate) if self.cursor_last_change.elapsed() > rate => {
+                self.cursor_active = !self.cursor_active;
+                self.cursor_last_change = Instant::now();
+            },
+            _ => {},
         }
-        if self.cursor_active && self.cursor_enabled {
-            self.canvas.set_draw_color(self.foreground_color);
-            let output_size = self.canvas.output_size().unwrap();
-            let scale_x = output_size.0 as f32 / self.current_video_mode.screen_width as f32;
-            let scale_y = output_size.1 as f32 / self.current_video_mode.screen_height as f32;
-            
-            self.canvas.fill_rect(Rect::new((self.cursor.position_x as f32 * scale_x) as i32, (self.cursor.position_y as f32 * scale_y) as i32, (8f32 * scale_x) as u32, (self.cursor.font_height as f32 * scale_y) as u32)).unwrap();
+    }
+
+    fn draw_cursor(&mut self) {
+        let shown = self.cursor_active || self.cursor_blink.is_none();
+        if shown && self.cursor_enabled && !self.text_at_graphics_cursor {
+            let (w, h) = (self.cursor.font_width, self.cursor.font_height);
+            let (left, width, top, bottom) = match self.cursor_style {
+                CursorStyle::Block => (0, w, self.cursor_start as i32, (self.cursor_end as i32).min(h - 1)),
+                CursorStyle::Underline => (0, w, h - (h / 8).max(1), h - 1),
+                CursorStyle::Bar => (0, (w / 4).max(1), self.cursor_start as i32, (self.cursor_end as i32).min(h - 1)),
+            };
+            if top <= bottom {
+                let rect = Rect::new(self.cursor.position_x + left, self.cursor.position_y + top, width as u32, (bottom - top + 1) as u32);
+                self.display.fill_rect(rect, self.palette[self.foreground_color as usize]);
+            }
         }
     }
 
+    // VDU 23,0,&0A: the start scanline in bits 0-4, bits 5-6 select a
+    // steady (0), hidden (1), fast (2) or slowly (3) blinking cursor.
+    fn set_cursor_start(&mut self, n: u8) {
+        self.cursor_start = n & 0x1F;
+        match n >> 5 & 3 {
+            0 => {
+                self.cursor_enabled = true;
+                self.cursor_blink = None;
+            },
+            1 => self.cursor_enabled = false,
+            2 => {
+                self.cursor_enabled = true;
+                self.cursor_blink = Some(CURSOR_BLINK_FAST);
+            },
+            _ => {
+                self.cursor_enabled = true;
+                self.cursor_blink = Some(CURSOR_BLINK_SLOW);
+            },
+        }
+    }
+
+    // VDU 23,1: 0 hides and 1 shows the cursor, 2 makes it steady and 3
+    // blinking, 4, 5 and 6 select the block, underline and bar styles.
+    fn set_cursor_mode(&mut self, n: u8) {
+        match n {
+            0 => self.cursor_enabled = false,
+            1 => self.cursor_enabled = true,
+            2 => self.cursor_blink = None,
+            3 => self.cursor_blink = Some(CURSOR_BLINK_SLOW),
+            4 => self.cursor_style = CursorStyle::Block,
+            5 => self.cursor_style = CursorStyle::Underline,
+            6 => self.cursor_style = CursorStyle::Bar,
+            _ => warn!("Unknown cursor mode {}", n),
+        }
+    }
+
+    fn compose_frame(&mut self) {
+        let shown = if self.current_video_mode.double_buffered { &self.front } else { &self.screen };
+        self.display = shown.map(|c| self.palette[c as usize]);
+        self.show_sprites();
+        self.draw_cursor();
+    }
+
 
     fn backspace(&mut self) {
         self.cursor.left();
@@ -376,34 +839,106 @@ impl VDP<'_> {
 
     
     fn cls(&mut self) {
-        self.canvas.with_texture_canvas(&mut self.texture, |texture_canvas| {
-            texture_canvas.set_draw_color(self.background_color);
-            texture_canvas.clear();
-        }).unwrap();
+        self.screen.fill_rect(self.cursor.window(), self.background_color);
+        if let Some(cells) = self.cells_in(self.cursor.window()) {
+            self.text.clear(cells);
+        }
         self.num_sprites = 0;
         self.num_sprites_shown = 0;
-        self.cursor.position_x = 0;
-        self.cursor.position_y = 0;
+        self.cursor.window_home();
         self.cursor.paged_count = 0;
     }
     
     fn clg(&mut self) {
-        self.canvas.with_texture_canvas(&mut self.texture, |texture_canvas| {
-            texture_canvas.set_draw_color(self.background_color);
-            texture_canvas.clear();
-        }).unwrap();
+        let w = self.graph_window;
+        let spans = graphics::rectangle_spans(w.top_left(), Point::new(w.right() - 1, w.bottom() - 1));
+        self.plot_spans(&spans, PlotAction::Background);
+    }
+
+    // VDU 24: graphics window, given in graphics coordinates.
+    fn set_graphics_window(&mut self, left: i16, bottom: i16, right: i16, top: i16) {
+        if left > right || bottom > top {
+            return;
+        }
+        let p1 = self.translate(self.scale(Point::new(left as i32, bottom as i32)));
+        let p2 = self.translate(self.scale(Point::new(right as i32, top as i32)));
+        let screen = Rect::new(0, 0, self.current_video_mode.screen_width, self.current_video_mode.screen_height);
+        let window = Rect::new(p1.x.min(p2.x), p1.y.min(p2.y), (p1.x - p2.x).unsigned_abs() + 1, (p1.y - p2.y).unsigned_abs() + 1);
+        if let Some(window) = window.intersection(screen) {
+            self.graph_window = window;
+        }
+    }
+
+    // VDU 28: text window, given in character cells.
+    fn set_text_window(&mut self, left: u8, bottom: u8, right: u8, top: u8) {
+        let (left, bottom, right, top) = (left as i32, bottom as i32, right as i32, top as i32);
+        if left > right || top > bottom ||
+            (right + 1) * self.cursor.font_width > self.cursor.screen_width ||
+            (bottom + 1) * self.cursor.font_height > self.cursor.screen_height {
+            return;
+        }
+        self.cursor.window_left = left * self.cursor.font_width;
+        self.cursor.window_top = top * self.cursor.font_height;
+        self.cursor.window_right = (right + 1) * self.cursor.font_width;
+        self.cursor.window_bottom = (bottom + 1) * self.cursor.font_height;
+        self.cursor.window_home();
+    }
+
+    // VDU 26: back to full screen windows and the default origin.
+    fn reset_windows(&mut self) {
+        self.cursor.reset_window();
+        self.cursor.window_home();
+        self.graph_window = Rect::new(0, 0, self.current_video_mode.screen_width, self.current_video_mode.screen_height);
+        self.graph_origin = Point::new(0, 0);
     }
 
     fn color(&mut self, c: u8) {
         if c < 128 {
-            self.foreground_color = *self.current_video_mode.palette[c as usize % self.current_video_mode.palette.len()];
+            self.foreground_color = self.color_index(c);
         } else {
-            self.background_color = *self.current_video_mode.palette[c as usize % self.current_video_mode.palette.len()];
+            self.background_color = self.color_index(c);
         }
     }
 
-    fn gcolor(&mut self, _m: u8, c: u8) {
-        self.graph_color = *self.current_video_mode.palette[c as usize % self.current_video_mode.palette.len()];
+    // Logical colour number c (with or without the background bit) in
+    // the current palette.
+    fn color_index(&self, c: u8) -> u8 {
+        ((c & 0x7F) as usize % self.palette.len()) as u8
+    }
+
+    // The palette entry closest to an RGB colour, for drawing bitmaps.
+    fn nearest_color(&self, c: Color) -> u8 {
+        let distance = |e: &Color| {
+            let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+            d(e.r, c.r) + d(e.g, c.g) + d(e.b, c.b)
+        };
+        self.palette.iter().enumerate().min_by_key(|(_, e)| distance(e)).map_or(0, |(i, _)| i as u8)
+    }
+
+    // VDU 19: make logical colour l show physical colour p, or the given
+    // RGB value if p is 255. Everything already drawn in that colour
+    // changes along with it.
+    fn define_color(&mut self, l: u8, p: u8, r: u8, g: u8, b: u8) {
+        let new = match p {
+            0..=63 => *PALETTE_64[p as usize],
+            255 => Color::RGB(r, g, b),
+            _ => {
+                warn!("Unsupported physical colour {}", p);
+                return;
+            },
+        };
+        let l = self.color_index(l);
+        self.palette[l as usize] = new;
+    }
+
+    fn gcolor(&mut self, m: u8, c: u8) {
+        if c < 128 {
+            self.graph_color = self.color_index(c);
+            self.graph_mode = m;
+        } else {
+            self.graph_bg_color = self.color_index(c);
+            self.graph_bg_mode = m;
+        }
     }    
 
     fn scale(&self, p: Point) -> Point {
@@ -490,154 +1025,334 @@ impl VDP<'_> {
         xc
     }
     
-    fn plot(&mut self, mode: u8, x: i16, y: i16) {
-        let p4 = self.p3;
-        self.p3 = self.p2;
-        self.p2 = self.p1;
-        self.p1 = self.translate(self.scale(Point::new(x as i32,y as i32)));
-        self.canvas.with_texture_canvas(&mut self.texture, |texture_canvas| {
-            texture_canvas.set_draw_color(self.graph_color);
-            match mode {
-                4 => {info!("MOVETO");},
-                5 => {
-                    info!("LINETO");
-                    texture_canvas.draw_line(self.p1,self.p2).unwrap();
-                },
-                64..=71 => {
-                    info!("PLOTDOT");
-                    texture_canvas.draw_point(self.p1).unwrap();
-                },
-                80..=87 => {
-                    info!("TRIANGLE");
-                    let mut ptop : Point = self.p1;
-                    let mut pmid : Point = self.p2;
-                    let mut pbot : Point = self.p3;
-                    // Order the points from top to bottom.
-                    if ptop.y > pmid.y
-                    {
-                        (ptop,pmid) = (pmid,ptop);
-                    }
-                    if ptop.y > pbot.y
-                    {
-                        (ptop,pbot) = (pbot,ptop);
-                    }
-                    if pmid.y > pbot.y
-                    {
-                        (pmid,pbot) = (pbot,pmid);
-                    }
-                    info!("Points are {},{}  {},{} {},{}",ptop.x,ptop.y,pmid.x,pmid.y,pbot.x,pbot.y);
-                    // Trace the line from top to bottom using Bresenham algo.
-                    // Also trace the lines from top via mid to bottom.
-                    // Draw horizontal lines between them.
-                    let xv1 = Self::line_xcoords(ptop, pbot);
-                    let mut xv2 = Self::line_xcoords(ptop, pmid);
-                    xv2.append(&mut Self::line_xcoords(pmid,pbot)[1..].to_vec());
-                    let mut y = ptop.y;
-                    for (i,x1) in xv1.iter().enumerate() {
-                        let x2 = xv2[i];
-                        texture_canvas.draw_line(Point::new(*x1,y),Point::new(x2,y)).unwrap();
-                        y += 1;
-                    }
-                },
-                144..=151 => {
-                    let r;
-                    if mode < 148 {
-                        r = ((self.p1.x * self.p1.x + self.p1.y * self.p1.y) as f32).sqrt();
-                    } else {
-                        let rx = self.p1.x - self.p2.x;
-                        let ry = self.p1.y - self.p2.y;
-                        r = ((rx*rx + ry*ry) as f32).sqrt();
-                    }
-                    info!("Circle at {},{} radius {}",self.p2.x, self.p2.y,r);
-                    let pstart = Point::new(self.p2.x + (r as i32), self.p2.y);
-                    let mut pold = pstart;
-                    let mut pnew = pold;
-                    // suboptimal implementaion of circle.
-                    for i in 1..32 {
-                        let angle = (i as f32) * 6.28318531 / 32.0;
-                        pold = pnew;
-                        pnew = Point::new(self.p2.x + ((r*angle.cos()) as i32),
-                                          self.p2.y + ((r*angle.sin()) as i32));
-                        texture_canvas.draw_line(pold,pnew).unwrap();                    
-                    }
-                    texture_canvas.draw_line(pnew,pstart).unwrap();
-                },
-                _ => {
-                    warn!("Unsupported plot mode!");
-                    // Reverting points to previous state.
-                    self.p1 = self.p2;
-                    self.p2 = self.p3;
-                    self.p3 = p4;
+    fn triangle_spans(p1: Point, p2: Point, p3: Point) -> Vec<Span> {
+        let mut ptop : Point = p1;
+        let mut pmid : Point = p2;
+        let mut pbot : Point = p3;
+        // Order the points from top to bottom.
+        if ptop.y > pmid.y
+        {
+            (ptop,pmid) = (pmid,ptop);
+        }
+        if ptop.y > pbot.y
+        {
+            (ptop,pbot) = (pbot,ptop);
+        }
+        if pmid.y > pbot.y
+        {
+            (pmid,pbot) = (pbot,pmid);
+        }
+        info!("Points are {},{}  {},{} {},{}",ptop.x,ptop.y,pmid.x,pmid.y,pbot.x,pbot.y);
+        // Trace the line from top to bottom using Bresenham algo.
+        // Also trace the lines from top via mid to bottom.
+        // Draw horizontal lines between them.
+        let xv1 = Self::line_xcoords(ptop, pbot);
+        let mut xv2 = Self::line_xcoords(ptop, pmid);
+        xv2.append(&mut Self::line_xcoords(pmid,pbot)[1..].to_vec());
+        (ptop.y..).zip(xv1.iter().zip(xv2.iter()))
+            .map(|(y, (x1, x2))| (y, *x1, *x2))
+            .collect()
+    }
+
+    // Logical inverse of a colour, i.e. the colour number with all bits
+    // inverted.
+    fn inverse_color(&self, c: u8) -> u8 {
+        c ^ (self.palette.len() - 1) as u8
+    }
+
+    // Combine the colour src with the pixel colour dst according to the
+    // GCOL mode. The logical operations work on the colour numbers.
+    fn gcol_combine(&self, mode: u8, dst: u8, src: u8) -> u8 {
+        let mask = (self.palette.len() - 1) as u8;
+        match mode {
+            1 => dst | src,
+            2 => dst & src,
+            3 => dst ^ src,
+            4 => self.inverse_color(dst),
+            5 => dst,
+            6 => dst & !src,
+            7 => (dst | !src) & mask,
+            _ => src,
+        }
+    }
+
+    // A pixel inside the graphics window.
+    fn graphics_pixel(&self, x: i32, y: i32) -> Option<u8> {
+        if self.graph_window.contains_point((x, y)) {
+            self.screen.get_pixel(x, y)
+        } else {
+            None
+        }
+    }
+
+    fn plot_pixel(&mut self, x: i32, y: i32, action: PlotAction) {
+        let dst = match self.graphics_pixel(x, y) {
+            Some(c) => c,
+            None => return,
+        };
+        let color = match action {
+            PlotAction::Foreground => self.gcol_combine(self.graph_mode, dst, self.graph_color),
+            PlotAction::Background => self.gcol_combine(self.graph_bg_mode, dst, self.graph_bg_color),
+            PlotAction::Inverse => self.inverse_color(dst),
+        };
+        self.screen.set_pixel(x, y, color);
+        self.text.set(x / self.cursor.font_width, y / self.cursor.font_height, None);
+    }
+
+    // Draw a bitmap onto the screen in the current GCOL mode. Its colours
+    // are mapped to the closest palette entries, fully transparent pixels
+    // are skipped.
+    fn draw_bitmap(&mut self, bitmap: &Framebuffer, x: i32, y: i32) {
+        for row in 0..bitmap.height() as i32 {
+            for col in 0..bitmap.width() as i32 {
+                let src = bitmap.get_pixel(col, row).unwrap();
+                if src.a == 0 {
+                    continue;
+                }
+                if let Some(dst) = self.graphics_pixel(x + col, y + row) {
+                    let c = self.gcol_combine(self.graph_mode, dst, self.nearest_color(src));
+                    self.screen.set_pixel(x + col, y + row, c);
+                    self.text.set((x + col) / self.cursor.font_width, (y + row) / self.cursor.font_height, None);
                 }
             }
-        }).unwrap();        
+        }
+    }
 
-    }    
+    // Points and spans may overlap, every pixel must be plotted only once
+    // or inverting would undo itself.
+    fn plot_points(&mut self, points: &[Point], action: PlotAction) {
+        let mut points = points.to_vec();
+        points.sort_by_key(|p| (p.y, p.x));
+        points.dedup();
+        for p in points.iter() {
+            self.plot_pixel(p.x, p.y, action);
+        }
+    }
 
-    fn get_screen_char(&mut self, x: i16, y: i16) -> u8 {
-        let mut c: u8 = 0;
-        if  x >= 0 &&
-            x < (self.cursor.screen_width/self.cursor.font_width) as i16 &&
-            y >= 0 &&
-            y <  (self.cursor.screen_height/self.cursor.font_height) as i16 {
-            self.canvas.with_texture_canvas(&mut self.texture, |texture_canvas| {
-                let rect = Rect::new((x*8) as i32, (y*8) as i32, 8, 8);
-                let v=texture_canvas.read_pixels(rect,PixelFormatEnum::RGB888).unwrap();
-                // Synthesize the character bytes from the read pixels.
-                // NOTE: we only do 8x8 chars for now!
-                let mut bitmap = vec![0 as u8; 8];
-                for cr in 0..8 {
-                    let mut b = 0;
-                    for cc in 0..8 {
-                        let vx = cr*8*4 + cc*4;
-                        let rgb = Color::RGB(v[vx+2],v[vx+1],v[vx+0]);
-                        b<<=1;
-                        if rgb == self.foreground_color {
-                            b |= 1;
-                        }
-                    }
-                    bitmap[cr] = b;
+    fn plot_spans(&mut self, spans: &[Span], action: PlotAction) {
+        let mut spans: Vec<Span> = spans.iter().map(|(y, x1, x2)| (*y, *x1.min(x2), *x1.max(x2))).collect();
+        spans.sort();
+        let mut merged: Vec<Span> = Vec::new();
+        for (y, x1, x2) in spans {
+            match merged.last_mut() {
+                Some(last) if last.0 == y && x1 <= last.2 + 1 => last.2 = last.2.max(x2),
+                _ => merged.push((y, x1, x2)),
+            }
+        }
+        let w = self.graph_window;
+        for (y, x1, x2) in merged {
+            for x in x1.max(w.left())..=x2.min(w.right() - 1) {
+                self.plot_pixel(x, y, action);
+            }
+        }
+    }
+
+    // Horizontal line through p1 over all pixels for which `fill` holds,
+    // to the left only if `left` is set. Nothing is drawn if p1 itself
+    // does not qualify.
+    fn line_fill(&mut self, left: bool, fill: impl Fn(u8) -> bool, action: PlotAction) {
+        let y = self.p1.y;
+        let fills = |x: i32| self.graphics_pixel(x, y).is_some_and(&fill);
+        if !fills(self.p1.x) {
+            return;
+        }
+        let mut x1 = self.p1.x;
+        let mut x2 = self.p1.x;
+        while left && fills(x1 - 1) {
+            x1 -= 1;
+        }
+        while fills(x2 + 1) {
+            x2 += 1;
+        }
+        self.plot_spans(&[(y, x1, x2)], action);
+    }
+
+    // Fill the area around p1 made of pixels for which `fill` holds.
+    fn flood_fill(&mut self, fill: impl Fn(u8) -> bool, action: PlotAction) {
+        let width = self.screen.width() as i32;
+        let mut seen = vec![false; (self.screen.width() * self.screen.height()) as usize];
+        let mut spans = Vec::new();
+        let mut todo = vec![self.p1];
+        while let Some(p) = todo.pop() {
+            let fills = |x: i32| self.graphics_pixel(x, p.y).is_some_and(&fill) && !seen[(p.y * width + x) as usize];
+            if !fills(p.x) {
+                continue;
+            }
+            let mut x1 = p.x;
+            let mut x2 = p.x;
+            while fills(x1 - 1) {
+                x1 -= 1;
+            }
+            while fills(x2 + 1) {
+                x2 += 1;
+            }
+            for x in x1..=x2 {
+                seen[(p.y * width + x) as usize] = true;
+                todo.push(Point::new(x, p.y - 1));
+                todo.push(Point::new(x, p.y + 1));
+            }
+            spans.push((p.y, x1, x2));
+        }
+        self.plot_spans(&spans, action);
+    }
+
+    // Move or copy the rectangle between p2 and p3 so that its bottom left
+    // corner ends up at p1.
+    fn copy_block(&mut self, move_block: bool) {
+        let x = self.p2.x.min(self.p3.x);
+        let y = self.p2.y.min(self.p3.y);
+        let w = (self.p2.x - self.p3.x).unsigned_abs() + 1;
+        let h = (self.p2.y - self.p3.y).unsigned_abs() + 1;
+        let source = self.screen.clone();
+        if move_block {
+            if let Some(area) = Rect::new(x, y, w, h).intersection(self.graph_window) {
+                self.screen.fill_rect(area, self.graph_bg_color);
+                self.text_drawn_over(area);
+            }
+        }
+        let dst = Rect::new(self.p1.x, self.p1.y - h as i32 + 1, w, h);
+        if let Some(clipped) = dst.intersection(self.graph_window) {
+            let src = Rect::new(x + clipped.x() - dst.x(), y + clipped.y() - dst.y(), clipped.width(), clipped.height());
+            self.screen.copy_from(&source, src, clipped);
+            self.text_drawn_over(clipped);
+        }
+    }
+
+    fn plot(&mut self, mode: u8, x: i16, y: i16) {
+        if mode > 207 {
+            warn!("Unsupported plot mode!");
+            return;
+        }
+        self.p3 = self.p2;
+        self.p2 = self.p1;
+        // Modes with bit 2 clear are relative to the last point visited.
+        if mode & 4 == 0 {
+            self.graph_cursor = Point::new(self.graph_cursor.x + x as i32, self.graph_cursor.y + y as i32);
+        } else {
+            self.graph_cursor = Point::new(x as i32, y as i32);
+        }
+        self.p1 = self.translate(self.scale(self.graph_cursor));
+        let action = match mode & 3 {
+            1 => PlotAction::Foreground,
+            2 => PlotAction::Inverse,
+            3 => PlotAction::Background,
+            _ => {
+                info!("MOVETO");
+                return;
+            },
+        };
+        let color = self.graph_color;
+        let bg_color = self.graph_bg_color;
+        match mode & 0xF8 {
+            0..=63 => {
+                info!("LINETO");
+                let mut points = graphics::line_points(self.p2, self.p1);
+                if mode & 0x10 != 0 {
+                    // Dotted line
+                    points = points.into_iter().step_by(2).collect();
                 }
-                // Find the bitmap in the character data.
-                for i in 0..96 {
-                    let pat = &self.font_data[i*8..i*8+8];
-                    if *pat == bitmap {
-                        c = i as u8  + 32;
-                        break;
-                    }
+                if mode & 0x08 != 0 && points.last() == Some(&self.p1) {
+                    points.pop();
+                }
+                if mode & 0x20 != 0 && points.first() == Some(&self.p2) {
+                    points.remove(0);
                 }
-            }).unwrap();            
+                self.plot_points(&points, action);
+            },
+            64 => {
+                info!("PLOTDOT");
+                self.plot_points(&[self.p1], action);
+            },
+            72 => self.line_fill(true, |c| c == bg_color, action),
+            80 => {
+                info!("TRIANGLE");
+                let spans = Self::triangle_spans(self.p1, self.p2, self.p3);
+                self.plot_spans(&spans, action);
+            },
+            88 => self.line_fill(false, |c| c != bg_color, action),
+            96 => {
+                info!("RECTANGLE");
+                self.plot_spans(&graphics::rectangle_spans(self.p1, self.p2), action);
+            },
+            104 => self.line_fill(true, |c| c != color, action),
+            112 => {
+                info!("PARALLELOGRAM");
+                let p4 = Point::new(self.p1.x - self.p2.x + self.p3.x, self.p1.y - self.p2.y + self.p3.y);
+                let mut spans = Self::triangle_spans(self.p1, self.p2, self.p3);
+                spans.append(&mut Self::triangle_spans(self.p1, p4, self.p3));
+                self.plot_spans(&spans, action);
+            },
+            120 => self.line_fill(false, |c| c == color, action),
+            128 => self.flood_fill(|c| c == bg_color, action),
+            136 => self.flood_fill(|c| c != color, action),
+            144 => {
+                let r = graphics::distance(self.p1, self.p2);
+                info!("Circle at {},{} radius {}",self.p2.x, self.p2.y,r);
+                self.plot_points(&graphics::circle_points(self.p2, r), action);
+            },
+            152 => {
+                let r = graphics::distance(self.p1, self.p2);
+                info!("Filled circle at {},{} radius {}",self.p2.x, self.p2.y,r);
+                self.plot_spans(&graphics::filled_circle_spans(self.p2, r, self.graph_window), action);
+            },
+            160 => {
+                info!("ARC");
+                self.plot_points(&graphics::arc_points(self.p3, self.p2, self.p1), action);
+            },
+            168 => {
+                info!("SEGMENT");
+                self.plot_spans(&graphics::segment_spans(self.p3, self.p2, self.p1, self.graph_window), action);
+            },
+            176 => {
+                info!("SECTOR");
+                self.plot_spans(&graphics::sector_spans(self.p3, self.p2, self.p1, self.graph_window), action);
+            },
+            184 => {
+                info!("BLOCK COPY");
+                self.copy_block(mode & 3 == 1);
+            },
+            192 => {
+                let rx = (self.p2.x - self.p3.x).abs();
+                let ry = (self.p1.y - self.p3.y).abs();
+                info!("Ellipse at {},{} radii {},{}", self.p3.x, self.p3.y, rx, ry);
+                self.plot_points(&graphics::ellipse_points(self.p3, rx, ry), action);
+            },
+            _ => {
+                let rx = (self.p2.x - self.p3.x).abs();
+                let ry = (self.p1.y - self.p3.y).abs();
+                info!("Filled ellipse at {},{} radii {},{}", self.p3.x, self.p3.y, rx, ry);
+                self.plot_spans(&graphics::filled_ellipse_spans(self.p3, rx, ry, self.graph_window), action);
+            },
         }
-        c
     }
 
-    fn get_screen_pixel(&mut self, x: i16, y: i16) -> Color {
-        let mut p1 = self.translate(self.scale(Point::new(x as i32,y as i32)));
-        let mut rgb = Color::RGB(0,0,0);
-        if p1.x >=0 && p1.x < self.current_video_mode.screen_width as i32 &&
-            p1.y >=0 && p1.y < self.current_video_mode.screen_height as i32 {
-                // Scale the point coordinates to the output size.
-                let output_size = self.canvas.output_size().unwrap();
-                let scale_x = output_size.0 as f32 / self.current_video_mode.screen_width as f32;
-                let scale_y = output_size.1 as f32 / self.current_video_mode.screen_height as f32;
-
-                p1.x = (p1.x as f32 * scale_x) as i32;
-                p1.y = (p1.y as f32 * scale_y) as i32;
+    fn get_screen_char(&mut self, x: i16, y: i16) -> u8 {
+        self.screen_char(x as i32, y as i32)
+    }
 
-                let rect = Rect::new(p1.x, p1.y, 1, 1);
-                let v = self.canvas.read_pixels(rect, PixelFormatEnum::RGB888).unwrap();
-                info!("Pixel at {},{} = {},{},{}", p1.x, p1.y, v[0], v[1], v[2]);
+    /// The character at a text position as read back with VDU 23,0,&83,
+    /// 0 outside of the screen.
+    pub fn screen_char(&self, column: i32, row: i32) -> u8 {
+        if column < 0 || row < 0 || column >= self.text.columns() || row >= self.text.rows() {
+            return 0;
+        }
+        // Cleared cells read as spaces.
+        self.text.get(column, row).map_or(b' ', |cell| cell.c)
+    }
 
-                rgb.r=v[2]; 
-                rgb.g=v[1]; 
-                rgb.b=v[0]; 
+    fn get_screen_pixel(&mut self, x: i16, y: i16) -> u8 {
+        let p1 = self.translate(self.scale(Point::new(x as i32,y as i32)));
+        let mut color = 0;
+        if let Some(c) = self.screen.get_pixel(p1.x, p1.y) {
+            info!("Pixel at {},{} = {}", p1.x, p1.y, c);
+            color = c;
         }
-        rgb
+        color
     }
 
     fn send_cursor_position(&self) {
-        let mut cursor_position_packet: Vec<u8> = vec![(self.cursor.position_x / self.cursor.font_width) as u8,
-        (self.cursor.position_y / self.cursor.font_height) as u8];
+        let mut cursor_position_packet: Vec<u8> = vec![((self.cursor.position_x - self.cursor.window_left) / self.cursor.font_width) as u8,
+        ((self.cursor.position_y - self.cursor.window_top) / self.cursor.font_height) as u8];
         self.send_packet(0x02, cursor_position_packet.len() as u8, &mut cursor_position_packet);	
     }
 
@@ -646,8 +1361,8 @@ impl VDP<'_> {
         self.send_packet(0x03, screen_char_packet.len() as u8, &mut screen_char_packet);	        
     }
 
-    fn send_screen_pixel(&self, rgb : Color) {
-        let c = self.current_video_mode.palette.iter().position(|&e| *e==rgb).unwrap() as u8;
+    fn send_screen_pixel(&self, c : u8) {
+        let rgb = self.palette[c as usize];
         let mut screen_pixel_packet: Vec<u8> = vec![rgb.r, rgb.g, rgb.b, c];
         self.send_packet(0x04, screen_pixel_packet.len() as u8, &mut screen_pixel_packet);	        
     }
@@ -663,26 +1378,43 @@ impl VDP<'_> {
         debug!("Send packet to MOS: {:#02X?}", output);
     }
 
+    // Only called once vdu::command_length says the whole command is
+    // buffered, so running out of bytes here is a bug in that table.
     fn read_byte(&mut self) -> u8 {
-        self.rx.recv().unwrap()
+        self.input.pop_front().unwrap_or_else(|| {
+            error!("Read past the end of a buffered VDU command!");
+            0
+        })
     }
 
-    fn try_read_byte(&mut self) -> Result<u8, TryRecvError> {
-        self.rx.try_recv()
+    // Move everything the host has sent so far into the input buffer and
+    // take the next command byte once the command is complete.
+    fn try_read_byte(&mut self) -> Option<u8> {
+        while let Ok(b) = self.rx.try_recv() {
+            self.input.push_back(b);
+        }
+        match vdu::command_length(&self.input, self.terminal_mode) {
+            Some(n) if self.input.len() >= n => self.input.pop_front(),
+            _ => None,
+        }
     }
 
     fn read_word(&mut self) -> i16 {
-        i16::from_le_bytes([self.rx.recv().unwrap(), self.rx.recv().unwrap()])
+        i16::from_le_bytes([self.read_byte(), self.read_byte()])
     } 
 
     fn read_long(&mut self) -> Color {
-        let b = [self.rx.recv().unwrap(), self.rx.recv().unwrap(),
-             self.rx.recv().unwrap(), self.rx.recv().unwrap()];
+        let b = [self.read_byte(), self.read_byte(),
+             self.read_byte(), self.read_byte()];
         Color::RGBA(b[0],b[1],b[2],b[3])
     } 
 
+    /// Process the next complete VDU command received from the host.
     /// @return true if data was received
-    fn do_comms(&mut self) -> bool {
+    pub fn do_comms(&mut self) -> bool {
+        if self.flip_pending {
+            return false; // nothing is drawn until the flip happened at vsync.
+        }
         if self.cursor.paged_mode {
             if self.cursor.paged_count == -2 {
                 return false; // do not process any bytes while waiting for shift key.
@@ -694,17 +1426,20 @@ impl VDP<'_> {
             }
         }
         match self.try_read_byte() {
-            Ok(n) => {
+            Some(n) => {
                 if self.terminal_mode {
                     self.print_terminal(n);
                 } else {    
                     match n {
+                        n if self.text_at_graphics_cursor && matches!(n, 0x08..=0x0B | 0x0D | 0x20..=0xFF) => {
+                            self.print_graphics(n);
+                        },
                         n if n >= 0x20 && n != 0x7F => {
                             info!("Received character: {}", n as char);
-                            self.render_char(n);
-                            self.cursor.right();
-                            self.check_scrolling_needed();
+                            self.print_char(n);
                         },
+                        0x04 => {info!("Text at text cursor."); self.text_at_graphics_cursor = false;},
+                        0x05 => {info!("Text at graphics cursor."); self.text_at_graphics_cursor = true;},
                         0x08 => {info!("Cursor left."); self.cursor.left();},
                         0x09 => {info!("Cursor right."); self.cursor.right();},
                         0x0A => {
@@ -742,15 +1477,15 @@ impl VDP<'_> {
                             let r = self.read_byte();
                             let g = self.read_byte();
                             let b = self.read_byte();
-                            info!("Define Logical Colour?: l:{} p:{} r:{} g:{} b:{}", l, p, r, g, b);
+                            info!("Define Logical Colour: l:{} p:{} r:{} g:{} b:{}", l, p, r, g, b);
+                            self.define_color(l, p, r, g, b);
                         },
                         0x16 => {
                             info!("MODE.");
                             let mode = self.read_byte();
-                            if mode >= VIDEO_MODES.len() as u8 {
-                                println!("Invalid mode: {}", mode);
-                            } else {
-                                self.change_mode(mode.into());
+                            match video_mode(mode) {
+                                Some(mode) => self.change_mode(mode),
+                                None => println!("Invalid mode: {}", mode),
                             }
                             self.send_mode_information();
                         },
@@ -763,30 +1498,54 @@ impl VDP<'_> {
                                 },
                                 0x01 => {
                                     let b = self.read_byte();
-                                    self.cursor_enabled = b != 0;
-                                    info!("Cursor Enable : P{}\n",self.cursor_enabled);
+                                    info!("Cursor mode {}", b);
+                                    self.set_cursor_mode(b);
+                                },
+                                0x10 => {
+                                    let x = self.read_byte();
+                                    let y = self.read_byte();
+                                    self.cursor.flags = (self.cursor.flags & y) ^ x;
+                                    info!("Cursor flags {:#04X}", self.cursor.flags);
                                 },
                                 0x07 =>  {
                                     let extent = self.read_byte();
                                     let d = self.read_byte();
                                     let m = self.read_byte();
                                     info!("Scroll: full {} dir {} movement {}",extent,d,m);
-                                    self.scroll(d, m);    
+                                    let area = match extent {
+                                        0 | 3 => self.cursor.window(),
+                                        2 => self.graph_window,
+                                        _ => Rect::new(0, 0, self.current_video_mode.screen_width, self.current_video_mode.screen_height),
+                                    };
+                                    self.scroll(area, d, m);
                                 },
                                 0x1B => {
                                     info!("Sprite Control");
                                     self.do_sprites();
                                 },
                                 n if n>=32 => {
-                                    for i in 0..8 {
-                                        let b =  self.read_byte();
-                                        self.font_data[((n-32)as u32*8+i) as usize] = b;
+                                    let mut rows = [0; 8];
+                                    for row in rows.iter_mut() {
+                                        *row = self.read_byte();
                                     }
+                                    self.font.redefine(n, &rows);
+                                    if let Some(font) = self.fonts.get_mut(&self.font_id) {
+                                        font.redefine(n, &rows);
+                                    }
+                                    self.glyph_cache.clear();
                                     info!("Redefine char bitmap: {}.", n);
                                 },
                                 n => { info!("Unknown VDU command: {:#02X?}.", n);}
                             }
                         },
+                        0x18 => {
+                            let left = self.read_word();
+                            let bottom = self.read_word();
+                            let right = self.read_word();
+                            let top = self.read_word();
+                            info!("Graphics window {},{},{},{}", left, bottom, right, top);
+                            self.set_graphics_window(left, bottom, right, top);
+                        },
                         0x19 => {
                             let mode = self.read_byte();
                             let x = self.read_word();
@@ -794,6 +1553,18 @@ impl VDP<'_> {
                             info!("PLOT {},{},{}",mode,x,y);
                             self.plot(mode,x,y);
                         },
+                        0x1A => {
+                            info!("Reset windows");
+                            self.reset_windows();
+                        },
+                        0x1C => {
+                            let left = self.read_byte();
+                            let bottom = self.read_byte();
+                            let right = self.read_byte();
+                            let top = self.read_byte();
+                            info!("Text window {},{},{},{}", left, bottom, right, top);
+                            self.set_text_window(left, bottom, right, top);
+                        },
                         0x1D => {
                             let x = self.read_word() as i32;
                             let y = self.read_word() as i32;
@@ -802,15 +1573,28 @@ impl VDP<'_> {
                             }
                             info!("Graph origin {},{}",x,y);
                         },
-                        0x1E => {info!("Home."); self.cursor.home();},
+                        0x1B => {
+                            let c = self.read_byte();
+                            info!("Print character {} as is.", c);
+                            // There are no glyphs for the control codes.
+                            if c >= 0x20 {
+                                if self.text_at_graphics_cursor {
+                                    self.draw_graphics_char(c);
+                                } else {
+                                    self.print_char(c);
+                                }
+                            }
+                        },
+                        0x1E => {info!("Home."); self.cursor.window_home();},
                         0x1F => {
-                            let x = self.read_byte() as i32 * self.cursor.font_width;
-                            let y = self.read_byte() as i32 * self.cursor.font_height;
+                            let x = self.cursor.window_left + self.read_byte() as i32 * self.cursor.font_width;
+                            let y = self.cursor.window_top + self.read_byte() as i32 * self.cursor.font_height;
                             info!("TAB({},{})",x,y);
-                            if x < self.cursor.screen_width && y < self.cursor.screen_height
+                            if x < self.cursor.window_right && y < self.cursor.window_bottom
                             {
                                 self.cursor.position_x = x;
                                 self.cursor.position_y = y;
+                                self.cursor.pending_wrap = false;
                             }
                         },
                         0x7F => {
@@ -822,13 +1606,22 @@ impl VDP<'_> {
                 }
                 true
             },
-            Err(TryRecvError::Empty) => false,
-            Err(TryRecvError::Disconnected) => false
+            None => false,
         }
     }
 
     fn video_system_control(&mut self) {
         match self.read_byte() {
+            0x0A => {
+                let n = self.read_byte();
+                info!("Cursor start {:#04X}", n);
+                self.set_cursor_start(n);
+            },
+            0x0B => {
+                let n = self.read_byte();
+                info!("Cursor end {}", n);
+                self.cursor_end = n;
+            },
             0x80 => {
                 info!("VDP_GP.");
                 self.general_poll();
@@ -865,8 +1658,8 @@ impl VDP<'_> {
                 let x = self.read_word();
                 let y = self.read_word();
                 info!("Get screen pixel at {},{}",x,y);
-                let rgb = self.get_screen_pixel(x,y);
-                self.send_screen_pixel(rgb);
+                let c = self.get_screen_pixel(x,y);
+                self.send_screen_pixel(c);
             },
             0x85 => {
                 info!("VDP_AUDIO");
@@ -891,11 +1684,24 @@ impl VDP<'_> {
                 info!("Keyboard State");
                 self.keyboard_state();
             },
+            0x95 => {
+                info!("Font control");
+                self.font_control();
+            },
+            0xA0 => {
+                info!("Buffer control");
+                self.buffer_control();
+            },
             0xC0 => {
                 let b = self.read_byte();
                 self.logical_coords = b != 0;
                 info!("Set logical coords {}", self.logical_coords);
             },
+            0xC3 => {
+                // In single buffered modes this only waits for vsync.
+                info!("Flip buffers");
+                self.flip_pending = true;
+            },
             0xff => {
                 info!("Switch to terminal mode");
                 self.switch_terminal_mode();
@@ -904,54 +1710,272 @@ impl VDP<'_> {
         }
     }
 
-    fn scroll(&mut self, direction: u8, delta: u8) {
-        let mut xsrc : i32 = 0;
-        let mut xdst : i32 = 0;
-        let mut ysrc : i32 = 0;
-        let mut ydst : i32 = 0;
-        let mut xsize : u32 = self.current_video_mode.screen_width;
-        let mut ysize: u32 = self.current_video_mode.screen_height;
+    // VDU 23,0,&95: fonts made from buffers.
+    fn font_control(&mut self) {
+        let command = self.read_byte();
+        let id = self.read_word() as u16;
+        match command {
+            0 => {
+                let _flags = self.read_byte();
+                info!("Select font {}", id);
+                self.select_font(id);
+            },
+            1 => {
+                let width = self.read_byte();
+                let height = self.read_byte();
+                let _ascent = self.read_byte();
+                let _flags = self.read_byte();
+                info!("Create font {} of {}x{}", id, width, height);
+                let data = self.buffers.get(&id).map(|buffer| buffer.as_slice()).unwrap_or(&[]);
+                match Font::parse(width as u32, height as u32, data) {
+                    Ok(font) => self.add_font(id, font),
+                    Err(e) => warn!("Cannot create font {}: {}", id, e),
+                }
+            },
+            4 => {
+                info!("Clear font {}", id);
+                if id != SYSTEM_FONT && id != TERMINAL_FONT {
+                    self.fonts.remove(&id);
+                }
+            },
+            n => warn!("Unknown font command {}", n),
+        }
+    }
 
-        match direction {
-            0 => { // right
-                xsize -= delta as u32;
-                xdst += delta as i32;
+    // VDU 23,0,&A0: buffers holding data sent by the eZ80.
+    fn buffer_control(&mut self) {
+        let id = self.read_word() as u16;
+        match self.read_byte() {
+            0 => {
+                let length = self.read_word() as u16;
+                info!("Write {} bytes to buffer {}", length, id);
+                let data: Vec<u8> = (0..length).map(|_| self.read_byte()).collect();
+                self.buffers.entry(id).or_default().extend(data);
             },
-            1 => { // left
-                xsize -= delta as u32;
-                xsrc += delta as i32;
+            2 => {
+                info!("Clear buffer {}", id);
+                self.buffers.remove(&id);
             },
-            2 => { // down
-                ysize -= delta as u32;
-                ydst += delta as i32;
+            n => warn!("Unknown buffer command {}", n),
+        }
+    }
+
+    // Move the content of `area` by delta pixels, the space left behind
+    // is cleared to the background colour.
+    fn scroll(&mut self, area: Rect, direction: u8, delta: u8) {
+        // Characters only stay characters when moved by whole cells.
+        if let Some(cells) = self.cells_in(area) {
+            let size = if direction < 2 { self.cursor.font_width } else { self.cursor.font_height };
+            if delta as i32 % size == 0 {
+                self.text.scroll(cells, direction, delta as i32 / size);
+            } else {
+                self.text.clear(cells);
+            }
+        }
+        let mut src = area;
+        let mut dst = area;
+        let delta = delta as u32;
+        match direction {
+            0 | 1 if delta < area.width() => { // right, left
+                src.set_width(area.width() - delta);
+                dst.set_width(area.width() - delta);
+                if direction == 0 {
+                    dst.set_x(area.x() + delta as i32);
+                } else {
+                    src.set_x(area.x() + delta as i32);
+                }
             },
-            3 => { // up
-                ysize -= delta as u32;
-                ysrc += delta as i32;
+            2 | 3 if delta < area.height() => { // down, up
+                src.set_height(area.height() - delta);
+                dst.set_height(area.height() - delta);
+                if direction == 2 {
+                    dst.set_y(area.y() + delta as i32);
+                } else {
+                    src.set_y(area.y() + delta as i32);
+                }
             },
-            _ => {}
+            0..=3 => {
+                self.screen.fill_rect(area, self.background_color);
+                return;
+            },
+            _ => return,
         }
-        let mut scrolled_texture = self.texture_creator.create_texture(None, sdl2::render::TextureAccess::Target, self.current_video_mode.screen_width, self.current_video_mode.screen_height).unwrap();
-        self.canvas.with_texture_canvas(&mut scrolled_texture, |texture_canvas| {
-            texture_canvas.set_draw_color(self.background_color);
-            texture_canvas.clear();
-            let rect_src = Rect::new(xsrc, ysrc, xsize, ysize);
-            let rect_dst = Rect::new(xdst, ydst, xsize, ysize);
-            texture_canvas.copy(&self.texture, rect_src, rect_dst).unwrap();
-        }).unwrap();        
-        self.texture = scrolled_texture;
+        let source = self.screen.clone();
+        self.screen.fill_rect(area, self.background_color);
+        self.screen.copy_from(&source, src, dst);
     }
 
+    // VDU 23,0,&85,channel,command: 0 plays a note, 1 asks for the channel
+    // status, 4 sets the waveform, 6 the volume and 7 the frequency
+    // envelope, 8 enables, 9 disables and 10 resets the channel. Command 5
+    // manages samples, given in place of the channel by the waveform
+    // number from 128 (-128) to 255 (-1) selecting them.
+    // Older VDPs took a waveform byte in place of the command which was
+    // always 0, so their notes still play.
     fn audio(&mut self) {
         let channel = self.read_byte();
-        let waveform = self.read_byte();
-        let volume = self.read_byte();
-        let frequency = self.read_word();
-        let duration = self.read_word();
-        info!("channel:{} waveform:{} volume:{} frequency:{} duration:{}", channel, waveform, volume, frequency, duration);
-        let res = self.audio_channels.start_tone(channel,waveform,volume,frequency,duration);
-        let mut audio_packet: Vec<u8> = vec![channel, res as u8];
-        self.send_packet(0x5, audio_packet.len() as u8, &mut audio_packet);
+        match self.read_byte() {
+            0 => {
+                let volume = self.read_byte();
+                let frequency = self.read_word() as u16;
+                let duration = self.read_word() as u16;
+                info!("channel:{} volume:{} frequency:{} duration:{}", channel, volume, frequency, duration);
+                let res = self.audio_channels.start_tone(channel,volume,frequency,duration);
+                let mut audio_packet: Vec<u8> = vec![channel, res as u8];
+                self.send_packet(0x5, audio_packet.len() as u8, &mut audio_packet);
+            },
+            1 => {
+                // 0 idle, 1 playing, 2 releasing, 255 for a disabled channel.
+                let status = match self.audio_channels.status(channel) {
+                    Some(ChannelState::Idle) => 0,
+                    Some(ChannelState::Playing) => 1,
+                    Some(ChannelState::Releasing) => 2,
+                    None => 255,
+                };
+                info!("channel:{} status:{}", channel, status);
+                let mut audio_packet: Vec<u8> = vec![channel, status];
+                self.send_packet(0x5, audio_packet.len() as u8, &mut audio_packet);
+            },
+            4 => {
+                let b = self.read_byte();
+                let waveform = if b == 8 {
+                    // The user sample: one cycle of signed 8 bit samples from a buffer.
+                    let id = self.read_word() as u16;
+                    let data = self.buffers.get(&id).map(|buffer| buffer.as_slice()).unwrap_or(&[]);
+                    Some(Waveform::Sample(data.iter().map(|s| *s as i8 as f32 / 128.0).collect()))
+                } else if b >= 128 {
+                    self.audio_channels.sample(b).map(Waveform::Pcm)
+                } else {
+                    Waveform::from_byte(b)
+                };
+                info!("channel:{} waveform:{:?}", channel, waveform);
+                match waveform {
+                    Some(waveform) => self.audio_channels.set_waveform(channel, waveform),
+                    None => warn!("Unknown waveform {}", b),
+                }
+            },
+            5 => self.audio_sample(channel),
+            6 => {
+                let envelope = match self.read_byte() {
+                    0 => None,
+                    1 => {
+                        let attack = self.read_word() as u16;
+                        let decay = self.read_word() as u16;
+                        let sustain = self.read_byte();
+                        let release = self.read_word() as u16;
+                        Some(VolumeEnvelope { attack, decay, sustain, release })
+                    },
+                    n => {
+                        warn!("Unknown volume envelope type {}", n);
+                        return;
+                    },
+                };
+                info!("channel:{} volume envelope:{:?}", channel, envelope);
+                self.audio_channels.set_volume_envelope(channel, envelope);
+            },
+            7 => {
+                let envelope = match self.read_byte() {
+                    0 => None,
+                    1 => {
+                        // Control bits: 1 repeats, 2 cumulative.
+                        let phase_count = self.read_byte();
+                        let control = self.read_byte();
+                        let step_length = self.read_word() as u16;
+                        let phases = (0..phase_count).map(|_| (self.read_word(), self.read_word() as u16)).collect();
+                        Some(FrequencyEnvelope { phases, repeats: control & 1 != 0, cumulative: control & 2 != 0, step_length })
+                    },
+                    n => {
+                        warn!("Unknown frequency envelope type {}", n);
+                        return;
+                    },
+                };
+                info!("channel:{} frequency envelope:{:?}", channel, envelope);
+                self.audio_channels.set_frequency_envelope(channel, envelope);
+            },
+            8 => {
+                info!("Enable channel {}", channel);
+                if !self.audio_channels.enable(channel) {
+                    warn!("No audio channel {}", channel);
+                }
+            },
+            9 => {
+                info!("Disable channel {}", channel);
+                self.audio_channels.disable(channel);
+            },
+            10 => {
+                info!("Reset channel {}", channel);
+                self.audio_channels.reset(channel);
+            },
+            n => warn!("Unknown audio command {}", n),
+        }
+    }
+
+    // VDU 23,0,&85,id,5,command: 0 uploads a sample, 2 makes one from a
+    // buffer, both given a format (0 unsigned, 1 signed 8 bit) and the
+    // sample rate. 1 removes the sample, 3 sets the part to loop and 4
+    // the base frequency at which it plays at its own rate.
+    fn audio_sample(&mut self, id: u8) {
+        let command = self.read_byte();
+        // The arguments are part of the command and read even for an
+        // invalid sample number.
+        let sample = match command {
+            0 => {
+                let signed = self.read_byte() & 1 != 0;
+                let rate = self.read_word() as u16;
+                let length = self.read_word() as u16 as usize | (self.read_byte() as usize) << 16;
+                let data: Vec<u8> = (0..length).map(|_| self.read_byte()).collect();
+                Some(PcmSample::from_bytes(&data, signed, rate))
+            },
+            2 => {
+                let signed = self.read_byte() & 1 != 0;
+                let rate = self.read_word() as u16;
+                let buffer = self.read_word() as u16;
+                Some(PcmSample::from_bytes(self.buffers.get(&buffer).map(|b| b.as_slice()).unwrap_or(&[]), signed, rate))
+            },
+            _ => None,
+        };
+        let (start, length) = if command == 3 {
+            (self.read_word() as u16 as usize | (self.read_byte() as usize) << 16,
+             self.read_word() as u16 as usize | (self.read_byte() as usize) << 16)
+        } else {
+            (0, 0)
+        };
+        let frequency = if command == 4 { self.read_word() as u16 } else { 0 };
+        if id < 128 {
+            warn!("Invalid sample number {}", id);
+            return;
+        }
+        match (command, sample) {
+            (_, Some(sample)) => {
+                info!("Sample {}: {} samples at {} Hz", id, sample.data.len(), sample.rate);
+                self.audio_channels.set_sample(id, Some(sample));
+            },
+            (1, _) => {
+                info!("Clear sample {}", id);
+                self.audio_channels.set_sample(id, None);
+            },
+            (3, _) => {
+                info!("Sample {} loops {} samples from {}", id, length, start);
+                if let Some(sample) = self.audio_channels.sample_mut(id) {
+                    sample.loop_start = start;
+                    sample.loop_length = length;
+                }
+            },
+            (4, _) => {
+                info!("Sample {} base frequency {}", id, frequency);
+                if let Some(sample) = self.audio_channels.sample_mut(id) {
+                    sample.base_frequency = frequency;
+                }
+            },
+            (n, _) => warn!("Unknown sample command {}", n),
+        }
+    }
+
+    /// A headless VDP has no audio device, its sound is mixed on request
+    /// instead: fill `out` with the next mono samples at 44.1 kHz. Does
+    /// nothing when the sound is played by an audio device.
+    pub fn render_audio(&mut self, out: &mut [f32]) {
+        self.audio_channels.render(out);
     }
 
     fn general_poll(&mut self) {
@@ -972,19 +1996,17 @@ impl VDP<'_> {
         if self.cursor.paged_mode && self.cursor.paged_count == -2 {
             return;
         }
-        let mut overdraw = self.cursor.position_y - self.current_video_mode.screen_height as i32 + self.cursor.font_height;
+        if self.cursor.position_y < self.cursor.window_top {
+            info!("Need to scroll down!");
+            self.scroll(self.cursor.window(), 2, self.cursor.font_height as u8);
+            self.cursor.position_y += self.cursor.font_height;
+            return;
+        }
+        let mut overdraw = self.cursor.position_y - self.cursor.window_bottom + self.cursor.font_height;
         if overdraw > 0 {
             overdraw = self.cursor.font_height; // Always scroll the entire height of the font.
             info!("Need to scroll! Overdraw: {}", overdraw);
-            let mut scrolled_texture = self.texture_creator.create_texture(None, sdl2::render::TextureAccess::Target, self.current_video_mode.screen_width, self.current_video_mode.screen_height).unwrap();
-            self.canvas.with_texture_canvas(&mut scrolled_texture, |texture_canvas| {
-                texture_canvas.set_draw_color(self.background_color);
-                texture_canvas.clear();
-                let rect_src = Rect::new(0, overdraw, self.current_video_mode.screen_width, self.current_video_mode.screen_height - overdraw as u32);
-                let rect_dst = Rect::new(0, 0, self.current_video_mode.screen_width, self.current_video_mode.screen_height - overdraw as u32);
-                texture_canvas.copy(&self.texture, rect_src, rect_dst).unwrap();
-            }).unwrap();
-            self.texture = scrolled_texture;
+            self.scroll(self.cursor.window(), 3, overdraw as u8);
             self.cursor.position_y -= overdraw;
         }
     }
@@ -999,6 +2021,7 @@ impl VDP<'_> {
             (self.cursor.screen_width / self.cursor.font_width) as u8,
             (self.cursor.screen_height / self.cursor.font_height) as u8,
             self.current_video_mode.colors,
+            self.current_video_mode.number,
          ];
         self.send_packet(0x06, packet.len() as u8, &mut packet);
     }
@@ -1033,20 +2056,14 @@ impl VDP<'_> {
                 let h = self.read_word() as i32;
                 info!("Read bitmap {} w={} h={}", self.current_bitmap,w,h);
                 if w > 0 && h > 0 {
-                    let mut tex = self.texture_creator.create_texture(PixelFormatEnum::RGBA8888, sdl2::render::TextureAccess::Static,w as u32,h  as u32).unwrap();
                     let mut pixel_data = Vec::new();
                     let bitmap_size = h*w;
                     for _i in 0..bitmap_size {
                         let c1 = self.read_long();
                         let c= self.color_quantize(c1);
-                        pixel_data.push(c.a);
-                        pixel_data.push(c.b);
-                        pixel_data.push(c.g);
-                        pixel_data.push(c.r);
+                        pixel_data.push(c);
                     }
-                    tex.update(None, &pixel_data, w as usize * 4).unwrap();
-                    tex.set_blend_mode(BlendMode::Blend);
-                    self.bitmaps[self.current_bitmap as usize ] = Some(tex);
+                    self.bitmaps[self.current_bitmap as usize ] = Some(Framebuffer::from_pixels(w as u32, h as u32, pixel_data));
                 }
             },
             2 => {
@@ -1054,20 +2071,11 @@ impl VDP<'_> {
                 let h = self.read_word() as i32;
                 info!("Read bitmap {} w={} h={} one colour", self.current_bitmap,w,h);
                 if w > 0 && h > 0 {
-                    let mut tex = self.texture_creator.create_texture(PixelFormatEnum::RGBA8888, sdl2::render::TextureAccess::Static,w as u32,h  as u32).unwrap();
                     let c1 = self.read_long();
                     let c=self.color_quantize(c1);
                     let bitmap_size = h*w;
-                    let mut pixel_data = Vec::new();
-                    for _i in 0..bitmap_size {
-                        pixel_data.push(c.a);
-                        pixel_data.push(c.b);
-                        pixel_data.push(c.g);
-                        pixel_data.push(c.r);
-                    }
-                    tex.update(None, &pixel_data, w as usize * 4).unwrap();
-                    tex.set_blend_mode(BlendMode::Blend);
-                    self.bitmaps[self.current_bitmap as usize ] = Some(tex);
+                    let pixel_data = vec![c; bitmap_size as usize];
+                    self.bitmaps[self.current_bitmap as usize ] = Some(Framebuffer::from_pixels(w as u32, h as u32, pixel_data));
                 }                
             },
             3 => {
@@ -1077,14 +2085,8 @@ impl VDP<'_> {
                 match &self.bitmaps[self.current_bitmap as usize] {
                     None => {error!("Undefined bitmap");},
                     Some(bm) => { 
-                        let q = bm.query();
-                        let sx = q.width;
-                        let sy = q.height;
-                        self.canvas.with_texture_canvas(&mut self.texture, |texture_canvas| {
-                            texture_canvas.copy(&bm,
-                                                None,
-                                                Some(Rect::new(x as i32,y as i32,sx,sy))).unwrap();
-                        }).unwrap();
+                        let bm = bm.clone();
+                        self.draw_bitmap(&bm, x as i32, y as i32);
                     },
                 }
             },
@@ -1205,19 +2207,11 @@ impl VDP<'_> {
     }
 
     fn show_sprites(&mut self) {
-        let output_size = self.canvas.output_size().unwrap();
-        let scale_x = output_size.0 as f32 / self.current_video_mode.screen_width as f32;
-        let scale_y = output_size.1 as f32 / self.current_video_mode.screen_height as f32;
         let mut idx=0;
         for s in self.sprites.iter() {
             if s.visible && idx < self.num_sprites_shown {
                 let bm = self.bitmaps[s.frames[s.current_frame as usize] as usize].as_ref().unwrap();
-                let q = bm.query();
-                let sx=q.width;
-                let sy=q.height;
-                self.canvas.copy(bm, None,
-                                 Rect::new((s.pos_x as f32 * scale_x) as i32, (s.pos_y as f32 * scale_y) as i32, sx * scale_x as u32, sy * scale_y as u32)                                 
-                ).unwrap();
+                self.display.blit(bm, s.pos_x as i32, s.pos_y as i32);
             }
             idx+=1;
         }
@@ -1229,11 +2223,9 @@ impl VDP<'_> {
 
     fn switch_terminal_mode(&mut self) {
         // Select 8x19 font on 640x480 mode.
-        self.font_data = FONT_8x19_BYTES[32*19..].to_vec();
-        self.cursor.font_height = 19;
-        self.cursor.font_width = 8;
-        self.change_mode(3); // This is different from real Agon, which supports termianl mode on top of any video mode.
-        self.foreground_color=Color::RGB(170, 170, 170);
+        self.select_font(TERMINAL_FONT);
+        self.change_mode(&VIDEO_MODES[0]); // This is different from real Agon, which supports termianl mode on top of any video mode.
+        self.foreground_color=7;
         self.tx.send(0).unwrap(); // CP/M waits for a byte to be returned.
         self.terminal_mode = true;
     }
@@ -1334,10 +2326,8 @@ impl VDP<'_> {
                                      self.clear_line(1);
                                  },
                                  2 => {
-                                     self.canvas.with_texture_canvas(&mut self.texture, |texture_canvas| {
-                                         texture_canvas.set_draw_color(self.background_color);
-                                         texture_canvas.clear();
-                                     }).unwrap();
+                                     self.screen.clear(self.background_color);
+                                     self.text = TextGrid::new(self.text.columns(), self.text.rows());
                                  },
                                  _ => {},
                              }                            
@@ -1368,14 +2358,14 @@ impl VDP<'_> {
                                  match attr {
                                      0 => { 
                                          info!("Normal");
-                                         self.foreground_color=Color::RGB(170, 170, 170);
-                                         self.background_color=Color::RGB(0, 0, 0);
+                                         self.foreground_color=7;
+                                         self.background_color=0;
                                          self.terminal_reverse = false;
                                          self.terminal_underline = false;
                                      },
                                      1 => {
                                          info!("Bold");
-                                         self.foreground_color=Color::RGB(255, 255, 255);
+                                         self.foreground_color=15;
                                      },
                                      4 => {
                                          info!("Underline");
@@ -1387,11 +2377,11 @@ impl VDP<'_> {
                                      },
                                      30..=37 => {
                                          info!("Foreground");
-                                         self.foreground_color = *self.current_video_mode.palette[(attr-30) as usize];                                         
+                                         self.foreground_color = self.color_index(attr-30);                                         
                                      },
                                      40..=47 => {
                                          info!("Background");
-                                         self.background_color = *self.current_video_mode.palette[(attr-40) as usize];                                         
+                                         self.background_color = self.color_index(attr-40);                                         
                                      },
                                      _ => {info!("Unimplemented attribute code {}",attr);},
                                  }
@@ -1419,20 +2409,26 @@ impl VDP<'_> {
             }
         }
         if dx > 0 {
-            self.canvas.with_texture_canvas(&mut self.texture, |texture_canvas| {
-                texture_canvas.set_draw_color(self.background_color);
-                texture_canvas.fill_rect(Rect::new(posx, posy, dx as u32, dy as u32)).unwrap();
-            }).unwrap();
+            self.screen.fill_rect(Rect::new(posx, posy, dx as u32, dy as u32), self.background_color);
+            self.text_drawn_over(Rect::new(posx, posy, dx as u32, dy as u32));
         }
     }
 
     fn clear_lines(&mut self, start: i32, h: i32) {
         if h>0 {
            let  w=self.cursor.screen_width;
-            self.canvas.with_texture_canvas(&mut self.texture, |texture_canvas| {
-                texture_canvas.set_draw_color(self.background_color);
-                texture_canvas.fill_rect(Rect::new(0, start, w as u32, h as u32)).unwrap();
-            }).unwrap();
+            self.screen.fill_rect(Rect::new(0, start, w as u32, h as u32), self.background_color);
+            self.text_drawn_over(Rect::new(0, start, w as u32, h as u32));
+        }
+    }
+
+    // Move the text lines from the cursor line down by n lines (direction
+    // 2) or up (direction 3), as done by insert_lines and delete_lines.
+    fn move_text_lines(&mut self, direction: u8, n: u8) {
+        let row = self.cursor.position_y / self.cursor.font_height;
+        if row < self.text.rows() {
+            let lines = Rect::new(0, row, self.text.columns() as u32, (self.text.rows() - row) as u32);
+            self.text.scroll(lines, direction, n as i32);
         }
     }
 
@@ -1444,17 +2440,14 @@ impl VDP<'_> {
         if scrolled <= 0 {
             scrolled = 0;
         }
-        let mut scrolled_texture = self.texture_creator.create_texture(None, sdl2::render::TextureAccess::Target, self.current_video_mode.screen_width, self.current_video_mode.screen_height).unwrap();
-        self.canvas.with_texture_canvas(&mut scrolled_texture, |texture_canvas| {
-            let rect_unchanged = Rect::new(0,0,width,start as u32);
-            texture_canvas.set_draw_color(self.background_color);
-            texture_canvas.clear();
-            texture_canvas.copy(&self.texture, rect_unchanged, rect_unchanged).unwrap();
-            let rect_src = Rect::new(0, start+blanks, width, scrolled as u32);
-            let rect_dst = Rect::new(0, start, width, scrolled as u32);
-            texture_canvas.copy(&self.texture, rect_src, rect_dst).unwrap();
-        }).unwrap();        
-        self.texture = scrolled_texture;        
+        let mut scrolled_screen = Framebuffer::new(self.current_video_mode.screen_width, self.current_video_mode.screen_height, self.background_color);
+        let rect_unchanged = Rect::new(0,0,width,start as u32);
+        scrolled_screen.copy_from(&self.screen, rect_unchanged, rect_unchanged);
+        let rect_src = Rect::new(0, start+blanks, width, scrolled as u32);
+        let rect_dst = Rect::new(0, start, width, scrolled as u32);
+        scrolled_screen.copy_from(&self.screen, rect_src, rect_dst);
+        self.screen = scrolled_screen;
+        self.move_text_lines(3, n);
     }
         
 
@@ -1466,17 +2459,14 @@ impl VDP<'_> {
         if scrolled <= 0 {
             scrolled = 0;
         }
-        let mut scrolled_texture = self.texture_creator.create_texture(None, sdl2::render::TextureAccess::Target, self.current_video_mode.screen_width, self.current_video_mode.screen_height).unwrap();
-        self.canvas.with_texture_canvas(&mut scrolled_texture, |texture_canvas| {
-            let rect_unchanged = Rect::new(0,0,width,start as u32);
-            texture_canvas.set_draw_color(self.background_color);
-            texture_canvas.clear();
-            texture_canvas.copy(&self.texture, rect_unchanged, rect_unchanged).unwrap();
-            let rect_src = Rect::new(0, start, width, scrolled as u32);
-            let rect_dst = Rect::new(0, start+blanks, width, scrolled as u32);
-            texture_canvas.copy(&self.texture, rect_src, rect_dst).unwrap();
-        }).unwrap();        
-        self.texture = scrolled_texture;        
+        let mut scrolled_screen = Framebuffer::new(self.current_video_mode.screen_width, self.current_video_mode.screen_height, self.background_color);
+        let rect_unchanged = Rect::new(0,0,width,start as u32);
+        scrolled_screen.copy_from(&self.screen, rect_unchanged, rect_unchanged);
+        let rect_src = Rect::new(0, start, width, scrolled as u32);
+        let rect_dst = Rect::new(0, start+blanks, width, scrolled as u32);
+        scrolled_screen.copy_from(&self.screen, rect_src, rect_dst);
+        self.screen = scrolled_screen;
+        self.move_text_lines(2, n);
     }
         
     // Parse the control codes following ESC
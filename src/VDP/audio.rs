@@ -1,138 +1,529 @@
 pub mod audio {
-    use sdl2::AudioSubsystem;
+    use std::collections::HashMap;
+    use std::sync::Arc;
     use std::sync::mpsc::{Sender, Receiver};
     use std::sync::mpsc;
-    use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioSpec, AudioDevice};
+    use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioDevice};
+
+    // Sample rate asked for from SDL and used by headless VDPs.
+    pub const SAMPLE_RATE: i32 = 44100;
+
+    /// Channels that can be enabled, the first 3 are enabled at start.
+    pub const MAX_CHANNELS: usize = 32;
+    const DEFAULT_CHANNELS: usize = 3;
+
+    /// What a channel is doing, as answered by the status command.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum ChannelState {
+        Idle,
+        Playing,
+        // The note ended, its volume envelope is fading out.
+        Releasing,
+    }
+
+    // Number of samples at `freq` in ms milliseconds. The product does not
+    // fit an i32 for the longest times.
+    fn ms_to_samples(ms: u16, freq: i32) -> i32 {
+        (ms as i64 * freq as i64 / 1000) as i32
+    }
+
+    /// Duration of a note that only ends when the channel is reset or
+    /// disabled, or with the end of a sample that does not loop.
+    pub const INDEFINITE: u16 = 0xFFFF;
+
+    /// A recorded sound uploaded by the host.
+    #[derive(Clone, Debug)]
+    pub struct PcmSample {
+        /// Samples from -1 to 1.
+        pub data: Vec<f32>,
+        /// Samples per second.
+        pub rate: u16,
+        /// The note frequency at which the sample plays at its own rate,
+        /// 0 to always play it at its own rate.
+        pub base_frequency: u16,
+        /// Part of the sample that repeats until the note ends, nothing
+        /// repeats with a length of 0.
+        pub loop_start: usize,
+        pub loop_length: usize,
+    }
+
+    impl PcmSample {
+        /// 8 bit PCM data, signed or unsigned.
+        pub fn from_bytes(data: &[u8], signed: bool, rate: u16) -> PcmSample {
+            let data = data.iter().map(|b| if signed { *b as i8 as f32 / 128.0 } else { (*b as f32 - 128.0) / 128.0 }).collect();
+            PcmSample { data, rate, base_frequency: 0, loop_start: 0, loop_length: 0 }
+        }
+    }
+
+    /// What a channel plays, chosen with VDU 23,0,&85,channel,4,waveform.
+    #[derive(Clone, Debug)]
+    pub enum Waveform {
+        Square,
+        Triangle,
+        Sawtooth,
+        Sine,
+        Noise,
+        /// One cycle of a user defined waveform, as samples from -1 to 1.
+        Sample(Vec<f32>),
+        /// A recorded sample, played once or looping.
+        Pcm(Arc<PcmSample>),
+    }
+
+    impl Waveform {
+        /// The waveform for the waveform byte of VDU 23,0,&85. 8 is the
+        /// user sample, which needs its data and is not known here.
+        pub fn from_byte(b: u8) -> Option<Waveform> {
+            match b {
+                0 => Some(Waveform::Square),
+                1 => Some(Waveform::Triangle),
+                2 => Some(Waveform::Sawtooth),
+                3 => Some(Waveform::Sine),
+                4 => Some(Waveform::Noise),
+                _ => None,
+            }
+        }
+
+        // The value at `phase`, 0 to 1 over one cycle, between -0.5 and 0.5.
+        fn value(&self, phase: f32, noise: &mut u32) -> f32 {
+            match self {
+                Waveform::Square => if phase < 0.5 { 0.5 } else { -0.5 },
+                Waveform::Triangle => if phase < 0.5 { phase * 2.0 - 0.5 } else { 1.5 - phase * 2.0 },
+                Waveform::Sawtooth => phase - 0.5,
+                Waveform::Sine => (phase * std::f32::consts::TAU).sin() * 0.5,
+                Waveform::Noise => {
+                    // xorshift32, white noise independent of the frequency.
+                    *noise ^= *noise << 13;
+                    *noise ^= *noise >> 17;
+                    *noise ^= *noise << 5;
+                    *noise as f32 / u32::MAX as f32 - 0.5
+                },
+                Waveform::Sample(data) if !data.is_empty() => {
+                    data[((phase * data.len() as f32) as usize).min(data.len() - 1)] * 0.5
+                },
+                Waveform::Sample(_) | Waveform::Pcm(_) => 0.0,
+            }
+        }
+    }
+
+    /// How the volume of a note develops, set with VDU 23,0,&85,channel,6.
+    /// Times are in milliseconds, the sustain level is a fraction of the
+    /// note volume from 0 to 255. The release follows the note duration.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct VolumeEnvelope {
+        pub attack: u16,
+        pub decay: u16,
+        pub sustain: u8,
+        pub release: u16,
+    }
+
+    /// Pitch changes during a note, set with VDU 23,0,&85,channel,7. Every
+    /// `step_length` milliseconds the frequency changes by the adjustment
+    /// of the current phase, each phase lasting its number of steps.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct FrequencyEnvelope {
+        /// (adjustment in Hz, number of steps) of every phase.
+        pub phases: Vec<(i16, u16)>,
+        /// Start over with the first phase after the last one.
+        pub repeats: bool,
+        /// On repeats carry on from the reached frequency instead of going
+        /// back to the note frequency.
+        pub cumulative: bool,
+        pub step_length: u16,
+    }
 
     // Parameters of a generated tone.
     #[derive(Clone)]
     struct WaveformState {
+        waveform: Waveform,
         period: f32,
         phase: f32,
         volume: f32,
         samples_to_go: i32,
+        noise: u32,
+        envelope: Option<VolumeEnvelope>,
+        frequency_envelope: Option<FrequencyEnvelope>,
+        // Steps of all phases of the frequency envelope.
+        frequency_steps: i32,
+        // Note frequency and the one reached with the frequency envelope.
+        base_frequency: f32,
+        frequency: f32,
+        // Samples played since the note started.
+        elapsed: i32,
+        // Position in a recorded sample.
+        position: f64,
+        // Samples left of the release after the note ended, and the level
+        // it started from.
+        release_to_go: i32,
+        release_level: f32,
+    }
+
+    impl WaveformState {
+        fn new() -> WaveformState {
+            WaveformState {
+                waveform: Waveform::Square,
+                period: 0.0,
+                phase: 0.0,
+                volume: 0.0,
+                samples_to_go: 0,
+                noise: 0x2545_F491,
+                envelope: None,
+                frequency_envelope: None,
+                frequency_steps: 0,
+                base_frequency: 0.0,
+                frequency: 0.0,
+                elapsed: 0,
+                position: 0.0,
+                release_to_go: 0,
+                release_level: 0.0,
+            }
+        }
+
+        // The value of the waveform for the current sample, between -0.5
+        // and 0.5, moving on in a recorded sample.
+        fn next_value(&mut self, freq: i32) -> f32 {
+            let Waveform::Pcm(sample) = &self.waveform else {
+                return self.waveform.value(self.phase / self.period, &mut self.noise);
+            };
+            let value = sample.data.get(self.position as usize).copied().unwrap_or(0.0) * 0.5;
+            let speed = if sample.base_frequency == 0 { 1.0 } else { self.frequency as f64 / sample.base_frequency as f64 };
+            self.position += sample.rate as f64 * speed / freq as f64;
+            let loop_end = sample.loop_start + sample.loop_length;
+            if sample.loop_length > 0 && loop_end <= sample.data.len() {
+                while self.position >= loop_end as f64 {
+                    self.position -= sample.loop_length as f64;
+                }
+            } else if self.position >= sample.data.len() as f64 {
+                // The note ends with the sample.
+                self.samples_to_go = self.samples_to_go.min(1);
+            }
+            value
+        }
+
+        fn playing(&self) -> bool {
+            self.samples_to_go > 0 || self.release_to_go > 0
+        }
+
+        // Factor of the volume from the envelope, for the current sample.
+        fn level(&self, freq: i32) -> f32 {
+            let Some(envelope) = self.envelope else {
+                return 1.0;
+            };
+            let samples = |ms: u16| ms_to_samples(ms, freq) as f32;
+            if self.samples_to_go == 0 {
+                return self.release_level * self.release_to_go as f32 / samples(envelope.release).max(1.0);
+            }
+            let t = self.elapsed as f32;
+            let (attack, decay) = (samples(envelope.attack), samples(envelope.decay));
+            let sustain = envelope.sustain as f32 / 255.0;
+            if t < attack {
+                t / attack
+            } else if t < attack + decay {
+                1.0 - (1.0 - sustain) * (t - attack) / decay
+            } else {
+                sustain
+            }
+        }
+
+        // Take the next frequency envelope step if one is due.
+        fn step_frequency(&mut self, freq: i32) {
+            let Some(envelope) = &self.frequency_envelope else {
+                return;
+            };
+            let step_samples = ms_to_samples(envelope.step_length, freq).max(1);
+            let total = self.frequency_steps;
+            if self.elapsed == 0 || self.elapsed % step_samples != 0 || total == 0 {
+                return;
+            }
+            let step = self.elapsed / step_samples - 1;
+            if step >= total && !envelope.repeats {
+                return;
+            }
+            let mut n = step % total;
+            if n == 0 && step > 0 && !envelope.cumulative {
+                self.frequency = self.base_frequency;
+            }
+            for (adjustment, steps) in envelope.phases.iter() {
+                if n < *steps as i32 {
+                    self.frequency = (self.frequency + *adjustment as f32).clamp(1.0, 65535.0);
+                    break;
+                }
+                n -= *steps as i32;
+            }
+            // Keep the position in the cycle.
+            let period = freq as f32 / self.frequency;
+            self.phase = self.phase / self.period * period;
+            self.period = period;
+        }
     }
 
     #[derive(Debug)]
-    struct GeneratorMessage {
-        channel: u8,
-        waveform: u8,
-        volume: u8,
-        frequency: i16,
-        duration: i16,
+    enum GeneratorMessage {
+        Play {
+            channel: u8,
+            volume: u8,
+            frequency: u16,
+            duration: u16,
+        },
+        Waveform {
+            channel: u8,
+            waveform: Waveform,
+        },
+        VolumeEnvelope {
+            channel: u8,
+            envelope: Option<VolumeEnvelope>,
+        },
+        FrequencyEnvelope {
+            channel: u8,
+            envelope: Option<FrequencyEnvelope>,
+        },
+        // Silence the channel and forget its waveform and envelopes.
+        Reset {
+            channel: u8,
+        },
     }
 
-    
+
     struct Generator {
         generators: Vec<WaveformState>,
         rx_fromVDP: Receiver<GeneratorMessage>,
-        tx_toVDP: Sender<u8>,
-        audio_spec: AudioSpec,
+        tx_toVDP: Sender<(u8, ChannelState)>,
+        freq: i32,
+    }
+
+    impl Generator {
+        fn new(rx: Receiver<GeneratorMessage>, tx: Sender<(u8, ChannelState)>, freq: i32) -> Generator {
+            Generator { generators: vec![WaveformState::new(); MAX_CHANNELS], rx_fromVDP: rx, tx_toVDP: tx, freq }
+        }
     }
 
     impl AudioCallback for Generator {
         type Channel = f32;
 
         fn callback(&mut self, out: &mut [f32]) {
-            while true {
-                match self.rx_fromVDP.try_recv() {
-                    Ok(msg) => {
-                        log::info!("Message received: {:?} freq {} buflen{}",msg,self.audio_spec.freq,out.len());
-                        let gen = &mut self.generators[msg.channel as usize];
-                        gen.period=(self.audio_spec.freq as f32)/(msg.frequency as f32);
+            while let Ok(msg) = self.rx_fromVDP.try_recv() {
+                log::info!("Message received: {:?} freq {} buflen{}",msg,self.freq,out.len());
+                match msg {
+                    GeneratorMessage::Play { channel, volume, frequency, duration } => {
+                        let gen = &mut self.generators[channel as usize];
+                        gen.base_frequency = frequency.max(1) as f32;
+                        gen.frequency = gen.base_frequency;
+                        gen.period = self.freq as f32 / gen.frequency;
                         gen.phase = 0.0;
-                        gen.volume = (msg.volume as f32)* (0.6/255.0);
-                        gen.samples_to_go = ((msg.duration as i32)*(self.audio_spec.freq as i32))/1000  as i32;
+                        gen.volume = (volume as f32)* (0.6/255.0);
+                        gen.samples_to_go = if duration == INDEFINITE { i32::MAX } else { ms_to_samples(duration, self.freq) };
+                        gen.position = 0.0;
+                        gen.elapsed = 0;
+                        gen.release_to_go = 0;
+                        let _ = self.tx_toVDP.send((channel, ChannelState::Playing));
+                    },
+                    GeneratorMessage::Waveform { channel, waveform } => {
+                        self.generators[channel as usize].waveform = waveform;
+                    },
+                    GeneratorMessage::VolumeEnvelope { channel, envelope } => {
+                        self.generators[channel as usize].envelope = envelope;
+                    },
+                    GeneratorMessage::FrequencyEnvelope { channel, envelope } => {
+                        let gen = &mut self.generators[channel as usize];
+                        gen.frequency_steps = envelope.as_ref().map_or(0, |e| e.phases.iter().map(|(_, steps)| *steps as i32).sum());
+                        gen.frequency_envelope = envelope;
+                    },
+                    GeneratorMessage::Reset { channel } => {
+                        self.generators[channel as usize] = WaveformState::new();
+                        let _ = self.tx_toVDP.send((channel, ChannelState::Idle));
                     },
-                    Err(_) => {break;},
                 }
             }
             for x in out.iter_mut() {
                 let mut sample = 0.0;
-                let mut cn=0;
-                for gen in self.generators.iter_mut() {
-                    if gen.samples_to_go > 0 {
-                        sample += (gen.phase - gen.period/2.0)/gen.period*gen.volume;
-                        gen.phase = gen.phase+1.0;
-                        if (gen.phase >= gen.period) {
+                for (cn, gen) in self.generators.iter_mut().enumerate() {
+                    if gen.playing() {
+                        let level = gen.level(self.freq);
+                        sample += gen.next_value(self.freq) * gen.volume * level;
+                        gen.phase += 1.0;
+                        if gen.phase >= gen.period {
                             gen.phase -= gen.period;
-                        }          
-                        gen.samples_to_go-=1;
-                        if gen.samples_to_go==0 {
-                            self.tx_toVDP.send(cn as u8);
+                        }
+                        gen.elapsed += 1;
+                        gen.step_frequency(self.freq);
+                        if gen.samples_to_go > 0 {
+                            gen.samples_to_go -= 1;
+                            if gen.samples_to_go == 0 {
+                                gen.release_to_go = gen.envelope.map_or(0, |e| ms_to_samples(e.release, self.freq));
+                                gen.release_level = level;
+                                if gen.release_to_go > 0 {
+                                    let _ = self.tx_toVDP.send((cn as u8, ChannelState::Releasing));
+                                }
+                            }
+                        } else {
+                            gen.release_to_go -= 1;
+                        }
+                        if !gen.playing() {
+                            let _ = self.tx_toVDP.send((cn as u8, ChannelState::Idle));
                         }
                     }
-                    cn += 1;
                 }
                 *x = sample;
             }
         }
     }
 
-    
+
     pub struct AudioChannels {
-        nchannels: i32,
-        channels_busy: Vec<bool>,
+        enabled: Vec<bool>,
+        // Channel states as last reported by the generator.
+        states: Vec<ChannelState>,
         tx_to_audio: Sender<GeneratorMessage>,
-        rx_from_audio: Receiver<u8>,
-        device: AudioDevice<Generator>,
+        rx_from_audio: Receiver<(u8, ChannelState)>,
+        // Uploaded samples by the (negative) waveform number selecting them.
+        samples: HashMap<u8, Arc<PcmSample>>,
+        device: Option<AudioDevice<Generator>>,
+        // Without an audio device the sound is only mixed on request.
+        headless: Option<Generator>,
     }
 
     impl AudioChannels {
-        
-        pub fn new(audio_subsystem: sdl2::AudioSubsystem) -> AudioChannels {
+
+        /// Without an audio subsystem nothing is played, the sound is
+        /// mixed by `render` instead.
+        pub fn new(audio_subsystem: Option<sdl2::AudioSubsystem>) -> AudioChannels {
             let (tx_VDP2audio, rx_VDP2audio): (Sender<GeneratorMessage>, Receiver<GeneratorMessage>) = mpsc::channel();
-            let (tx_audio2VDP, rx_audio2VDP): (Sender<u8>, Receiver<u8>) = mpsc::channel();
+            let (tx_audio2VDP, rx_audio2VDP) = mpsc::channel::<(u8, ChannelState)>();
             let desired_spec = AudioSpecDesired {
-                freq: Some(44100),
+                freq: Some(SAMPLE_RATE),
                 channels: Some(1),
                 samples: None,
             };
-            let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-            Generator {
-                generators: vec![WaveformState{period: 0.0, phase: 0.0, volume: 0.0, samples_to_go: 0 };3],
-                rx_fromVDP: rx_VDP2audio,
-                tx_toVDP: tx_audio2VDP,
-                audio_spec: spec
-            }}).unwrap();
-            device.resume();
-            AudioChannels{nchannels: 3,
-                          channels_busy: vec![false, false, false],
+            let (device, headless) = match audio_subsystem {
+                Some(audio_subsystem) => {
+                    let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
+                        Generator::new(rx_VDP2audio, tx_audio2VDP, spec.freq)
+                    }).unwrap();
+                    device.resume();
+                    (Some(device), None)
+                },
+                None => (None, Some(Generator::new(rx_VDP2audio, tx_audio2VDP, SAMPLE_RATE))),
+            };
+            AudioChannels{enabled: (0..MAX_CHANNELS).map(|n| n < DEFAULT_CHANNELS).collect(),
+                          states: vec![ChannelState::Idle; MAX_CHANNELS],
                           tx_to_audio: tx_VDP2audio,
                           rx_from_audio: rx_audio2VDP,
-                          device: device,
+                          samples: HashMap::new(),
+                          device,
+                          headless,
             }
         }
 
-        pub fn start_tone(&mut self, channel: u8, waveform: u8, volume: u8,
-                          frequency: i16, duration: i16) -> bool {
-            while true {
-                match self.rx_from_audio.try_recv() {
-                    Ok(b) => {
-                        if (b as i32)  < self.nchannels {
-                            // Clear the busy state if a generator reports finished.
-                            self.channels_busy[b as usize] = false;
-                        }
-                    },
-                    Err(_) => {break;}
-                }
+        /// Mix the next samples into `out` when there is no audio device.
+        pub fn render(&mut self, out: &mut [f32]) {
+            if let Some(generator) = self.headless.as_mut() {
+                generator.callback(out);
+            }
+        }
+
+        fn is_enabled(&self, channel: u8) -> bool {
+            self.enabled.get(channel as usize).copied().unwrap_or(false)
+        }
+
+        // Take in what the generator reported since the last time.
+        fn update_states(&mut self) {
+            while let Ok((channel, state)) = self.rx_from_audio.try_recv() {
+                self.states[channel as usize] = state;
             }
-            if ((channel as i32) >= self.nchannels || self.channels_busy[channel as usize]) {
+        }
+
+        pub fn start_tone(&mut self, channel: u8, volume: u8,
+                          frequency: u16, duration: u16) -> bool {
+            self.update_states();
+            if !self.is_enabled(channel) || self.states[channel as usize] != ChannelState::Idle {
                 false
             } else {
                 log::info!("Trying to play note on chan {} vol {} freq {} duration {}",channel,volume,frequency,duration);
                 if duration > 0 {
-                    self.tx_to_audio.send(GeneratorMessage {
-                        channel: channel,
-                        waveform: waveform,
-                        volume: volume,
-                        frequency: frequency,
-                        duration: duration});
-                    self.channels_busy[channel as usize] = true;
+                    let _ = self.tx_to_audio.send(GeneratorMessage::Play {
+                        channel,
+                        volume,
+                        frequency,
+                        duration});
+                    self.states[channel as usize] = ChannelState::Playing;
                 }
                 true
             }
         }
+
+        pub fn set_waveform(&mut self, channel: u8, waveform: Waveform) {
+            if self.is_enabled(channel) {
+                let _ = self.tx_to_audio.send(GeneratorMessage::Waveform { channel, waveform });
+            }
+        }
+
+        /// Shape the volume of the following notes on the channel, None
+        /// plays them at a constant volume.
+        pub fn set_volume_envelope(&mut self, channel: u8, envelope: Option<VolumeEnvelope>) {
+            if self.is_enabled(channel) {
+                let _ = self.tx_to_audio.send(GeneratorMessage::VolumeEnvelope { channel, envelope });
+            }
+        }
+
+        /// Change the pitch during the following notes on the channel,
+        /// None keeps it at the note frequency.
+        pub fn set_frequency_envelope(&mut self, channel: u8, envelope: Option<FrequencyEnvelope>) {
+            if self.is_enabled(channel) {
+                let _ = self.tx_to_audio.send(GeneratorMessage::FrequencyEnvelope { channel, envelope });
+            }
+        }
+
+        /// Store a sample under waveform number `id`, or remove it with
+        /// None. Channels playing it keep their copy.
+        pub fn set_sample(&mut self, id: u8, sample: Option<PcmSample>) {
+            match sample {
+                Some(sample) => self.samples.insert(id, Arc::new(sample)),
+                None => self.samples.remove(&id),
+            };
+        }
+
+        /// Change the looping or base frequency of a stored sample, taking
+        /// effect when a channel selects it the next time.
+        pub fn sample_mut(&mut self, id: u8) -> Option<&mut PcmSample> {
+            self.samples.get_mut(&id).map(Arc::make_mut)
+        }
+
+        pub fn sample(&self, id: u8) -> Option<Arc<PcmSample>> {
+            self.samples.get(&id).cloned()
+        }
+
+        /// What the channel is doing, None if it is not enabled.
+        pub fn status(&mut self, channel: u8) -> Option<ChannelState> {
+            self.update_states();
+            self.is_enabled(channel).then(|| self.states[channel as usize])
+        }
+
+        /// Make one of the MAX_CHANNELS channels available, returns false
+        /// for a channel number out of range.
+        pub fn enable(&mut self, channel: u8) -> bool {
+            match self.enabled.get_mut(channel as usize) {
+                Some(enabled) => {
+                    *enabled = true;
+                    true
+                },
+                None => false,
+            }
+        }
+
+        /// Stop the channel and take it out of use.
+        pub fn disable(&mut self, channel: u8) {
+            if self.is_enabled(channel) {
+                self.reset(channel);
+                self.enabled[channel as usize] = false;
+            }
+        }
+
+        /// Stop the channel and go back to a square wave without envelopes.
+        pub fn reset(&mut self, channel: u8) {
+            if self.is_enabled(channel) {
+                let _ = self.tx_to_audio.send(GeneratorMessage::Reset { channel });
+                self.states[channel as usize] = ChannelState::Idle;
+            }
+        }
     }
-    
+
 }
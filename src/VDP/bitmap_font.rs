@@ -0,0 +1,186 @@
+use std::path::Path;
+
+/// Largest width and height of a font, as in the VDU 23,0,&95 command.
+const MAX_FONT_SIZE: u32 = 255;
+
+/// A bitmap font for the character codes 0 to 255. Every glyph is
+/// `height` rows of `(width + 7) / 8` bytes, the most significant bit
+/// being the leftmost pixel.
+#[derive(Clone)]
+pub struct Font {
+    width: u32,
+    height: u32,
+    glyphs: Vec<u8>,
+}
+
+impl Font {
+    /// Raw glyph data of the given size, starting at character `first`.
+    /// Characters missing at the end are left blank.
+    pub fn from_raw(width: u32, height: u32, first: u8, data: &[u8]) -> Result<Font, String> {
+        if width == 0 || height == 0 || width > MAX_FONT_SIZE || height > MAX_FONT_SIZE {
+            return Err(format!("Invalid font size {}x{}", width, height));
+        }
+        let mut font = Font::blank(width, height);
+        let size = font.glyph_size();
+        let start = first as usize * size;
+        let n = data.len().min(font.glyphs.len() - start);
+        font.glyphs[start..start + n].copy_from_slice(&data[..n]);
+        Ok(font)
+    }
+
+    /// A PC Screen Font, version 1 or 2.
+    pub fn from_psf(data: &[u8]) -> Result<Font, String> {
+        let word = |i: usize| data.get(i..i + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        let (width, height, start, glyphs, glyph_size) = match data {
+            [0x36, 0x04, mode, height, ..] => (8, *height as u32, 4, if mode & 1 != 0 { 512 } else { 256 }, *height as u32),
+            [0x72, 0xb5, 0x4a, 0x86, ..] => {
+                let header = word(8).ok_or("Truncated PSF header")?;
+                let glyphs = word(16).ok_or("Truncated PSF header")?;
+                let glyph_size = word(20).ok_or("Truncated PSF header")?;
+                let height = word(24).ok_or("Truncated PSF header")?;
+                let width = word(28).ok_or("Truncated PSF header")?;
+                (width, height, header as usize, glyphs, glyph_size)
+            },
+            _ => return Err("Not a PSF font".to_string()),
+        };
+        if width == 0 || height == 0 || width > MAX_FONT_SIZE || height > MAX_FONT_SIZE {
+            return Err(format!("Invalid font size {}x{}", width, height));
+        }
+        if glyph_size != width.div_ceil(8) * height {
+            return Err(format!("PSF glyphs of {} bytes for a {}x{} font", glyph_size, width, height));
+        }
+        let size = glyphs.checked_mul(glyph_size).ok_or("Invalid PSF glyph count")?;
+        let glyphs = data.get(start..).filter(|g| g.len() as u64 >= size as u64).ok_or("Truncated PSF font")?;
+        Font::from_raw(width, height, 0, glyphs)
+    }
+
+    /// A font in the Glyph Bitmap Distribution Format. Glyphs are placed
+    /// in a cell the size of the font bounding box, on a common baseline.
+    pub fn from_bdf(text: &str) -> Result<Font, String> {
+        let numbers = |line: &str| -> Vec<i32> { line.split_whitespace().skip(1).filter_map(|n| n.parse().ok()).collect() };
+        let mut font: Option<Font> = None;
+        let mut bounds = (0, 0, 0, 0);
+        let mut encoding = -1;
+        let mut bbx = (0, 0, 0, 0);
+        let mut row: Option<i32> = None;
+        for line in text.lines().map(|l| l.trim()) {
+            let keyword = line.split_whitespace().next().unwrap_or("");
+            match (keyword, row) {
+                ("FONTBOUNDINGBOX", _) => {
+                    if let [w, h, x, y] = numbers(line)[..] {
+                        if !(1..=MAX_FONT_SIZE as i32).contains(&w) || !(1..=MAX_FONT_SIZE as i32).contains(&h) {
+                            return Err(format!("Invalid font size {}x{}", w, h));
+                        }
+                        bounds = (w, h, x, y);
+                        font = Some(Font::blank(w as u32, h as u32));
+                    }
+                },
+                ("ENCODING", _) => encoding = numbers(line).first().copied().unwrap_or(-1),
+                ("BBX", _) => {
+                    if let [w, h, x, y] = numbers(line)[..] {
+                        bbx = (w, h, x, y);
+                    }
+                },
+                ("BITMAP", _) => row = Some(0),
+                ("ENDCHAR", _) => row = None,
+                (_, Some(r)) => {
+                    let font = font.as_mut().ok_or("BDF glyph before FONTBOUNDINGBOX")?;
+                    if line.len() > 16 {
+                        return Err(format!("BDF bitmap row {} is too long", line));
+                    }
+                    let bits = u64::from_str_radix(line, 16).map_err(|_| format!("Bad BDF bitmap row {}", line))?;
+                    let top = (bounds.1 + bounds.3) - (bbx.1 + bbx.3);
+                    for x in 0..bbx.0.min(line.len() as i32 * 4) {
+                        if bits & (1 << (line.len() as i32 * 4 - 1 - x)) != 0 && (0..256).contains(&encoding) {
+                            font.set_pixel(encoding as u8, bbx.2 - bounds.2 + x, top + r, true);
+                        }
+                    }
+                    row = Some(r + 1);
+                },
+                _ => {},
+            }
+        }
+        font.ok_or_else(|| "Not a BDF font".to_string())
+    }
+
+    /// Load a font file: PSF and BDF fonts by their extension, anything
+    /// else is taken as raw 8 pixel wide glyphs for all 256 characters.
+    pub fn load(path: &Path) -> Result<Font, String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("psf") => Font::from_psf(&data),
+            Some("bdf") => Font::from_bdf(&String::from_utf8_lossy(&data)),
+            _ if !data.is_empty() && data.len() % 256 == 0 => Font::from_raw(8, (data.len() / 256) as u32, 0, &data),
+            _ => Err(format!("{}: not a font", path.display())),
+        }
+    }
+
+    /// Guess the format from the data, for fonts sent by the eZ80.
+    pub fn parse(width: u32, height: u32, data: &[u8]) -> Result<Font, String> {
+        if data.starts_with(&[0x36, 0x04]) || data.starts_with(&[0x72, 0xb5, 0x4a, 0x86]) {
+            Font::from_psf(data)
+        } else if data.starts_with(b"STARTFONT") {
+            Font::from_bdf(&String::from_utf8_lossy(data))
+        } else {
+            Font::from_raw(width, height, 0, data)
+        }
+    }
+
+    fn blank(width: u32, height: u32) -> Font {
+        let mut font = Font { width, height, glyphs: Vec::new() };
+        font.glyphs = vec![0; font.glyph_size() * 256];
+        font
+    }
+
+    fn glyph_size(&self) -> usize {
+        (self.width as usize).div_ceil(8) * self.height as usize
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The glyphs of all 256 characters, as taken by `from_raw`.
+    pub fn data(&self) -> &[u8] {
+        &self.glyphs
+    }
+
+    fn bit(&self, c: u8, x: i32, y: i32) -> Option<(usize, u8)> {
+        if x < 0 || y < 0 || x as u32 >= self.width || y as u32 >= self.height {
+            return None;
+        }
+        let row = (self.width as usize).div_ceil(8);
+        Some((c as usize * self.glyph_size() + y as usize * row + x as usize / 8, 0x80 >> (x % 8)))
+    }
+
+    /// Is the pixel at x,y of character c set.
+    pub fn pixel(&self, c: u8, x: i32, y: i32) -> bool {
+        self.bit(c, x, y).is_some_and(|(i, mask)| self.glyphs[i] & mask != 0)
+    }
+
+    pub fn set_pixel(&mut self, c: u8, x: i32, y: i32, set: bool) {
+        if let Some((i, mask)) = self.bit(c, x, y) {
+            if set {
+                self.glyphs[i] |= mask;
+            } else {
+                self.glyphs[i] &= !mask;
+            }
+        }
+    }
+
+    /// Replace character c by an 8x8 bitmap as sent with VDU 23, scaled
+    /// to the size of the font.
+    pub fn redefine(&mut self, c: u8, rows: &[u8; 8]) {
+        for y in 0..self.height as i32 {
+            for x in 0..self.width as i32 {
+                let (sx, sy) = (x * 8 / self.width as i32, y * 8 / self.height as i32);
+                let set = rows[sy as usize] & (0x80 >> sx) != 0;
+                self.set_pixel(c, x, y, set);
+            }
+        }
+    }
+}
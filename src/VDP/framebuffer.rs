@@ -0,0 +1,138 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+/// A software drawing surface. All VDU drawing happens on one of these,
+/// independent of any window system; a `Renderer` only gets to see the
+/// finished frame. The screen holds palette indices, the frame that is
+/// presented and the bitmaps for sprites hold RGBA colours.
+#[derive(Clone)]
+pub struct Framebuffer<P = Color> {
+    width: u32,
+    height: u32,
+    pixels: Vec<P>,
+}
+
+impl<P: Copy + PartialEq> Framebuffer<P> {
+    pub fn new(width: u32, height: u32, color: P) -> Framebuffer<P> {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![color; (width * height) as usize],
+        }
+    }
+
+    /// Create a framebuffer from row-major pixel data.
+    pub fn from_pixels(width: u32, height: u32, pixels: Vec<P>) -> Framebuffer<P> {
+        assert_eq!(pixels.len(), (width * height) as usize, "Pixel data does not match size");
+        Framebuffer { width, height, pixels }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// All pixels, row by row.
+    pub fn pixels(&self) -> &[P] {
+        &self.pixels
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x >= 0 && y >= 0 && (x as u32) < self.width && (y as u32) < self.height {
+            Some(y as usize * self.width as usize + x as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn get_pixel(&self, x: i32, y: i32) -> Option<P> {
+        self.index(x, y).map(|i| self.pixels[i])
+    }
+
+    /// Set a single pixel, points outside the surface are ignored.
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: P) {
+        if let Some(i) = self.index(x, y) {
+            self.pixels[i] = color;
+        }
+    }
+
+    pub fn clear(&mut self, color: P) {
+        self.pixels.fill(color);
+    }
+
+    /// Clip a rectangle to the surface, returning (x0, y0, x1, y1) with
+    /// exclusive upper bounds, or None if nothing is left.
+    fn clip(&self, rect: Rect) -> Option<(i32, i32, i32, i32)> {
+        let x0 = rect.x().max(0);
+        let y0 = rect.y().max(0);
+        let x1 = (rect.x() + rect.width() as i32).min(self.width as i32);
+        let y1 = (rect.y() + rect.height() as i32).min(self.height as i32);
+        if x0 < x1 && y0 < y1 {
+            Some((x0, y0, x1, y1))
+        } else {
+            None
+        }
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, color: P) {
+        if let Some((x0, y0, x1, y1)) = self.clip(rect) {
+            for y in y0..y1 {
+                let row = y as usize * self.width as usize;
+                self.pixels[row + x0 as usize..row + x1 as usize].fill(color);
+            }
+        }
+    }
+
+    /// Copy the `src` area of `source` to the `dst` area of this surface.
+    /// The areas are expected to have the same size; anything falling
+    /// outside of either surface is skipped.
+    pub fn copy_from(&mut self, source: &Framebuffer<P>, src: Rect, dst: Rect) {
+        let w = src.width().min(dst.width()) as i32;
+        let h = src.height().min(dst.height()) as i32;
+        // Columns that are on both surfaces.
+        let col0 = 0.max(-src.x()).max(-dst.x());
+        let col1 = w.min(source.width as i32 - src.x()).min(self.width as i32 - dst.x());
+        if col0 >= col1 {
+            return;
+        }
+        for row in 0..h {
+            if let (Some(s), Some(d)) = (source.index(src.x() + col0, src.y() + row), self.index(dst.x() + col0, dst.y() + row)) {
+                let n = (col1 - col0) as usize;
+                self.pixels[d..d + n].copy_from_slice(&source.pixels[s..s + n]);
+            }
+        }
+    }
+
+    /// A framebuffer of the same size with `f` applied to every pixel.
+    pub fn map<Q>(&self, f: impl Fn(P) -> Q) -> Framebuffer<Q> {
+        Framebuffer {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.iter().map(|p| f(*p)).collect(),
+        }
+    }
+}
+
+impl Framebuffer<Color> {
+    /// Draw `bitmap` with its top left corner at x,y, blending it with
+    /// the existing content according to the bitmap's alpha channel.
+    pub fn blit(&mut self, bitmap: &Framebuffer, x: i32, y: i32) {
+        for row in 0..bitmap.height as i32 {
+            for col in 0..bitmap.width as i32 {
+                let src = bitmap.pixels[(row * bitmap.width as i32 + col) as usize];
+                if src.a == 0 {
+                    continue;
+                }
+                if let Some(i) = self.index(x + col, y + row) {
+                    let dst = self.pixels[i];
+                    let a = src.a as u32;
+                    let mix = |s: u8, d: u8| ((s as u32 * a + d as u32 * (255 - a)) / 255) as u8;
+                    self.pixels[i] = Color::RGBA(mix(src.r, dst.r), mix(src.g, dst.g), mix(src.b, dst.b), dst.a);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,247 @@
+// Rasterisation of the PLOT shapes, in screen coordinates (y growing
+// downwards). Outlines are returned as lists of points, filled shapes as
+// horizontal spans (y, x_left, x_right) with both ends included.
+
+use std::f32::consts::PI;
+use std::ops::RangeInclusive;
+
+use sdl2::rect::{Point, Rect};
+
+pub type Span = (i32, i32, i32);
+
+/// All points of the line from p1 to p2, in drawing order.
+pub fn line_points(p1: Point, p2: Point) -> Vec<Point> {
+    let mut points = Vec::new();
+    let dx = (p2.x - p1.x).abs();
+    let dy = -(p2.y - p1.y).abs();
+    let sx = if p1.x < p2.x { 1 } else { -1 };
+    let sy = if p1.y < p2.y { 1 } else { -1 };
+    let mut err = dx + dy;
+    let (mut x, mut y) = (p1.x, p1.y);
+    loop {
+        points.push(Point::new(x, y));
+        if x == p2.x && y == p2.y {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+    points
+}
+
+pub fn rectangle_spans(p1: Point, p2: Point) -> Vec<Span> {
+    let (x1, x2) = (p1.x.min(p2.x), p1.x.max(p2.x));
+    (p1.y.min(p2.y)..=p1.y.max(p2.y)).map(|y| (y, x1, x2)).collect()
+}
+
+pub fn distance(p1: Point, p2: Point) -> i32 {
+    let dx = (p1.x - p2.x) as f32;
+    let dy = (p1.y - p2.y) as f32;
+    (dx * dx + dy * dy).sqrt().round() as i32
+}
+
+/// Midpoint circle outline.
+pub fn circle_points(centre: Point, r: i32) -> Vec<Point> {
+    let mut points = Vec::new();
+    let mut x = r;
+    let mut y = 0;
+    let mut err = 1 - r;
+    while x >= y {
+        for (px, py) in [(x, y), (y, x), (-y, x), (-x, y), (-x, -y), (-y, -x), (y, -x), (x, -y)] {
+            points.push(Point::new(centre.x + px, centre.y + py));
+        }
+        y += 1;
+        if err < 0 {
+            err += 2 * y + 1;
+        } else {
+            x -= 1;
+            err += 2 * (y - x) + 1;
+        }
+    }
+    points.sort_by_key(|p| (p.y, p.x));
+    points.dedup();
+    points
+}
+
+/// Is the point inside the circle, on the same grid as circle_points.
+fn in_circle(dx: i32, dy: i32, r: i32) -> bool {
+    let (dx, dy, r) = (dx as i64, dy as i64, r as i64);
+    dx * dx + dy * dy <= r * r + r
+}
+
+// Half the width of the filled circle at dy, for dy within -r..=r.
+fn circle_half_width(dy: i32, r: i32) -> i32 {
+    let (dy64, r64) = (dy as i64, r as i64);
+    let mut w = ((r64 * r64 + r64 - dy64 * dy64) as f64).sqrt() as i32;
+    while w > 0 && !in_circle(w, dy, r) {
+        w -= 1;
+    }
+    while in_circle(w + 1, dy, r) {
+        w += 1;
+    }
+    w
+}
+
+// The offsets from -r to r that stay within low..high from `at`, so that
+// shapes far bigger than the screen only cost the part that is visible.
+fn clipped(at: i32, r: i32, low: i32, high: i32) -> RangeInclusive<i32> {
+    (-r).max(low - at)..=r.min(high - 1 - at)
+}
+
+/// The filled circle, limited to the rows and columns of `clip`.
+pub fn filled_circle_spans(centre: Point, r: i32, clip: Rect) -> Vec<Span> {
+    let mut spans = Vec::new();
+    for dy in clipped(centre.y, r, clip.top(), clip.bottom()) {
+        let w = circle_half_width(dy, r);
+        let xs = clipped(centre.x, w, clip.left(), clip.right());
+        if !xs.is_empty() {
+            spans.push((centre.y + dy, centre.x + xs.start(), centre.x + xs.end()));
+        }
+    }
+    spans
+}
+
+/// Angle of a point as seen from the centre, anticlockwise on the screen
+/// and in the range 0..2PI.
+fn angle(centre: Point, p: Point) -> f32 {
+    let a = ((centre.y - p.y) as f32).atan2((p.x - centre.x) as f32);
+    if a < 0.0 { a + 2.0 * PI } else { a }
+}
+
+/// Is the angle a within the arc going anticlockwise from start to end.
+/// Equal start and end angles make a full circle.
+fn in_arc(a: f32, start: f32, end: f32) -> bool {
+    let span = (end - start).rem_euclid(2.0 * PI);
+    span == 0.0 || (a - start).rem_euclid(2.0 * PI) <= span
+}
+
+/// Arc around `centre` from `start` anticlockwise to the direction of
+/// `end`; the radius is given by the start point.
+pub fn arc_points(centre: Point, start: Point, end: Point) -> Vec<Point> {
+    let (a1, a2) = (angle(centre, start), angle(centre, end));
+    circle_points(centre, distance(centre, start)).into_iter()
+        .filter(|p| in_arc(angle(centre, *p), a1, a2))
+        .collect()
+}
+
+/// The area between an arc and its chord.
+pub fn segment_spans(centre: Point, start: Point, end: Point, clip: Rect) -> Vec<Span> {
+    let r = distance(centre, start);
+    let (a1, a2) = (angle(centre, start), angle(centre, end));
+    let chord_start = Point::new(centre.x + (r as f32 * a1.cos()).round() as i32, centre.y - (r as f32 * a1.sin()).round() as i32);
+    let chord_end = Point::new(centre.x + (r as f32 * a2.cos()).round() as i32, centre.y - (r as f32 * a2.sin()).round() as i32);
+    let mid = a1 + (a2 - a1).rem_euclid(2.0 * PI) / 2.0;
+    let mid = Point::new(centre.x + (r as f32 * mid.cos()) as i32, centre.y - (r as f32 * mid.sin()) as i32);
+    let side = |p: Point| (chord_end.x - chord_start.x) as i64 * (p.y - chord_start.y) as i64
+        - (chord_end.y - chord_start.y) as i64 * (p.x - chord_start.x) as i64;
+    let arc_side = side(mid).signum();
+    masked_circle_spans(centre, r, clip, |p| {
+        let s = side(p).signum();
+        s == 0 || s == arc_side
+    })
+}
+
+/// The pie slice between the radii to start and end.
+pub fn sector_spans(centre: Point, start: Point, end: Point, clip: Rect) -> Vec<Span> {
+    let (a1, a2) = (angle(centre, start), angle(centre, end));
+    masked_circle_spans(centre, distance(centre, start), clip, |p| p == centre || in_arc(angle(centre, p), a1, a2))
+}
+
+// Spans covering the pixels of the filled circle within `clip` for which
+// `inside` holds.
+fn masked_circle_spans<F: Fn(Point) -> bool>(centre: Point, r: i32, clip: Rect, inside: F) -> Vec<Span> {
+    let mut spans = Vec::new();
+    for dy in clipped(centre.y, r, clip.top(), clip.bottom()) {
+        let xs = clipped(centre.x, circle_half_width(dy, r), clip.left(), clip.right());
+        let y = centre.y + dy;
+        let mut start = None;
+        for dx in xs.clone() {
+            let p = Point::new(centre.x + dx, y);
+            match (inside(p), start) {
+                (true, None) => start = Some(p.x),
+                (false, Some(x)) => {
+                    spans.push((y, x, p.x - 1));
+                    start = None;
+                },
+                _ => {},
+            }
+        }
+        if let Some(x) = start {
+            spans.push((y, x, centre.x + xs.end()));
+        }
+    }
+    spans
+}
+
+/// Midpoint ellipse outline with the given radii.
+pub fn ellipse_points(centre: Point, rx: i32, ry: i32) -> Vec<Point> {
+    let mut points = Vec::new();
+    let mut plot4 = |x: i32, y: i32| {
+        for (px, py) in [(x, y), (-x, y), (x, -y), (-x, -y)] {
+            points.push(Point::new(centre.x + px, centre.y + py));
+        }
+    };
+    let (rx2, ry2) = (rx as i64 * rx as i64, ry as i64 * ry as i64);
+    let (mut x, mut y) = (0i64, ry as i64);
+    let mut px = 0i64;
+    let mut py = 2 * rx2 * y;
+    // Region 1, slope flatter than -1.
+    let mut p = ry2 - rx2 * ry as i64 + rx2 / 4;
+    while px < py {
+        plot4(x as i32, y as i32);
+        x += 1;
+        px += 2 * ry2;
+        if p < 0 {
+            p += ry2 + px;
+        } else {
+            y -= 1;
+            py -= 2 * rx2;
+            p += ry2 + px - py;
+        }
+    }
+    // Region 2, slope steeper than -1.
+    // The terms are too big for i64 with radii near the 16 bit coordinate
+    // range, their difference is not.
+    let (x128, y128) = (x as i128, y as i128);
+    let mut p = (ry2 as i128 * (2 * x128 + 1) * (2 * x128 + 1) / 4 + rx2 as i128 * (y128 - 1) * (y128 - 1) - rx2 as i128 * ry2 as i128) as i64;
+    while y >= 0 {
+        plot4(x as i32, y as i32);
+        y -= 1;
+        py -= 2 * rx2;
+        if p > 0 {
+            p += rx2 - py;
+        } else {
+            x += 1;
+            px += 2 * ry2;
+            p += rx2 - py + px;
+        }
+    }
+    points.sort_by_key(|p| (p.y, p.x));
+    points.dedup();
+    points
+}
+
+/// The filled ellipse, limited to the rows and columns of `clip`.
+pub fn filled_ellipse_spans(centre: Point, rx: i32, ry: i32, clip: Rect) -> Vec<Span> {
+    let mut spans = Vec::new();
+    for dy in clipped(centre.y, ry, clip.top(), clip.bottom()) {
+        let w = if ry == 0 {
+            rx
+        } else {
+            let f = 1.0 - (dy as f64 * dy as f64) / (ry as f64 * ry as f64);
+            (rx as f64 * f.max(0.0).sqrt()).round() as i32
+        };
+        let xs = clipped(centre.x, w, clip.left(), clip.right());
+        if !xs.is_empty() {
+            spans.push((centre.y + dy, centre.x + xs.start(), centre.x + xs.end()));
+        }
+    }
+    spans
+}
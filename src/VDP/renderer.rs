@@ -0,0 +1,29 @@
+use super::framebuffer::Framebuffer;
+
+/// Output side of the VDP. The VDP draws everything into a `Framebuffer`
+/// and hands the finished frame (including sprites and the text cursor)
+/// to a renderer once per vsync.
+pub trait Renderer {
+    /// Called whenever the video mode, and so the frame size, changes.
+    fn set_mode(&mut self, width: u32, height: u32);
+    /// Show a finished frame.
+    fn present(&mut self, frame: &Framebuffer);
+}
+
+/// A renderer without any display, for running the VDP on machines
+/// without a window system. The frame can be inspected through
+/// `VDP::frame`.
+#[derive(Default)]
+pub struct HeadlessRenderer;
+
+impl HeadlessRenderer {
+    pub fn new() -> HeadlessRenderer {
+        HeadlessRenderer
+    }
+}
+
+impl Renderer for HeadlessRenderer {
+    fn set_mode(&mut self, _width: u32, _height: u32) {}
+
+    fn present(&mut self, _frame: &Framebuffer) {}
+}
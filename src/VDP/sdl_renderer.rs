@@ -0,0 +1,55 @@
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+
+use super::framebuffer::Framebuffer;
+use super::renderer::Renderer;
+
+/// Shows the VDP output in an SDL2 window, scaled by `scale_window`.
+pub struct SdlRenderer<'a> {
+    canvas: Canvas<Window>,
+    texture_creator: &'a TextureCreator<WindowContext>,
+    texture: Texture<'a>,
+    scale_window: u8,
+}
+
+impl<'a> SdlRenderer<'a> {
+    pub fn new(canvas: Canvas<Window>, texture_creator: &'a TextureCreator<WindowContext>, scale_window: u8) -> SdlRenderer<'a> {
+        let (width, height) = canvas.output_size().unwrap();
+        let texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGBA32, width, height).unwrap();
+        SdlRenderer {
+            canvas,
+            texture_creator,
+            texture,
+            scale_window,
+        }
+    }
+}
+
+impl Renderer for SdlRenderer<'_> {
+    fn set_mode(&mut self, width: u32, height: u32) {
+        self.canvas.window_mut().set_size(width * self.scale_window as u32, height * self.scale_window as u32).unwrap();
+        self.texture = self.texture_creator.create_texture_streaming(PixelFormatEnum::RGBA32, width, height).unwrap();
+    }
+
+    fn present(&mut self, frame: &Framebuffer) {
+        let query = self.texture.query();
+        if query.width != frame.width() || query.height != frame.height() {
+            self.texture = self.texture_creator.create_texture_streaming(PixelFormatEnum::RGBA32, frame.width(), frame.height()).unwrap();
+        }
+        let width = frame.width() as usize;
+        self.texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+            for (y, row) in frame.pixels().chunks(width).enumerate() {
+                for (x, c) in row.iter().enumerate() {
+                    let offset = y * pitch + x * 4;
+                    buffer[offset..offset + 4].copy_from_slice(&[c.r, c.g, c.b, 0xFF]);
+                }
+            }
+        }).unwrap();
+        let result = self.canvas.copy(&self.texture, None, None);
+        if result.is_err() {
+            panic!("Fail!");
+        }
+        self.canvas.present();
+    }
+}
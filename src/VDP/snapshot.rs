@@ -0,0 +1,410 @@
+//! Save and restore the complete state of the VDP.
+//!
+//! A snapshot is a little endian binary dump starting with the magic
+//! bytes "AGVS" and a format version. Audio and the keyboard layout are
+//! not part of it, neither is anything still queued from the host.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+
+use super::text_grid::{Cell, TextGrid};
+use super::{video_mode, Cursor, CursorStyle, Font, Framebuffer, Sprite, SYSTEM_FONT, TERMINAL_FONT, VDP};
+
+const MAGIC: &[u8; 4] = b"AGVS";
+const VERSION: u16 = 1;
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, n: u8) {
+        self.0.push(n);
+    }
+
+    fn bool(&mut self, b: bool) {
+        self.u8(b as u8);
+    }
+
+    fn u16(&mut self, n: u16) {
+        self.0.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.0.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn i32(&mut self, n: i32) {
+        self.0.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn bytes(&mut self, data: &[u8]) {
+        self.u32(data.len() as u32);
+        self.0.extend_from_slice(data);
+    }
+
+    fn point(&mut self, p: Point) {
+        self.i32(p.x);
+        self.i32(p.y);
+    }
+
+    fn rect(&mut self, r: Rect) {
+        self.i32(r.x());
+        self.i32(r.y());
+        self.u32(r.width());
+        self.u32(r.height());
+    }
+
+    fn font(&mut self, font: &Font) {
+        self.u32(font.width());
+        self.u32(font.height());
+        self.bytes(font.data());
+    }
+}
+
+struct Reader<'d> {
+    data: &'d [u8],
+    pos: usize,
+}
+
+impl<'d> Reader<'d> {
+    fn take(&mut self, n: usize) -> Result<&'d [u8], String> {
+        let end = self.pos.checked_add(n).ok_or("Truncated snapshot")?;
+        let bytes = self.data.get(self.pos..end).ok_or("Truncated snapshot")?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn bytes(&mut self) -> Result<&'d [u8], String> {
+        let n = self.u32()? as usize;
+        self.take(n)
+    }
+
+    fn point(&mut self) -> Result<Point, String> {
+        Ok(Point::new(self.i32()?, self.i32()?))
+    }
+
+    fn rect(&mut self) -> Result<Rect, String> {
+        Ok(Rect::new(self.i32()?, self.i32()?, self.u32()?, self.u32()?))
+    }
+
+    fn font(&mut self) -> Result<Font, String> {
+        let width = self.u32()?;
+        let height = self.u32()?;
+        Font::from_raw(width, height, 0, self.bytes()?)
+    }
+
+    fn screen(&mut self, width: u32, height: u32) -> Result<Framebuffer<u8>, String> {
+        Ok(Framebuffer::from_pixels(width, height, self.take((width * height) as usize)?.to_vec()))
+    }
+}
+
+impl VDP<'_> {
+    /// The complete state of the VDP as a versioned binary blob, to be
+    /// given to `restore` later, e.g. for save states.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut w = Writer(MAGIC.to_vec());
+        w.u16(VERSION);
+
+        w.u8(self.current_video_mode.number);
+        w.u8(self.palette.len() as u8);
+        for c in self.palette.iter() {
+            w.u8(c.r);
+            w.u8(c.g);
+            w.u8(c.b);
+        }
+        w.0.extend_from_slice(self.screen.pixels());
+        w.0.extend_from_slice(self.front.pixels());
+        w.bool(self.flip_pending);
+
+        for c in [self.foreground_color, self.background_color, self.graph_color, self.graph_bg_color, self.graph_mode, self.graph_bg_mode] {
+            w.u8(c);
+        }
+        for b in [self.logical_coords, self.terminal_mode, self.text_at_graphics_cursor, self.terminal_underline, self.terminal_reverse] {
+            w.bool(b);
+        }
+
+        let cursor = &self.cursor;
+        for n in [cursor.position_x, cursor.position_y, cursor.font_width, cursor.font_height, cursor.paged_count,
+                  cursor.window_left, cursor.window_top, cursor.window_right, cursor.window_bottom] {
+            w.i32(n);
+        }
+        w.bool(cursor.paged_mode);
+        w.u8(cursor.flags);
+        w.bool(cursor.pending_wrap);
+        w.bool(self.cursor_enabled);
+        w.u8(match self.cursor_style {
+            CursorStyle::Block => 0,
+            CursorStyle::Underline => 1,
+            CursorStyle::Bar => 2,
+        });
+        w.u8(self.cursor_start);
+        w.u8(self.cursor_end);
+        w.u32(self.cursor_blink.map_or(0, |d| d.as_millis() as u32));
+
+        for p in [self.graph_cursor, self.p1, self.p2, self.p3, self.graph_origin] {
+            w.point(p);
+        }
+        w.rect(self.graph_window);
+
+        w.font(&self.font);
+        w.u16(self.font_id);
+        let mut fonts: Vec<_> = self.fonts.iter().collect();
+        fonts.sort_by_key(|(id, _)| **id);
+        w.u32(fonts.len() as u32);
+        for (id, font) in fonts {
+            w.u16(*id);
+            w.font(font);
+        }
+        let mut buffers: Vec<_> = self.buffers.iter().collect();
+        buffers.sort_by_key(|(id, _)| **id);
+        w.u32(buffers.len() as u32);
+        for (id, data) in buffers {
+            w.u16(*id);
+            w.bytes(data);
+        }
+
+        w.i32(self.text.columns());
+        w.i32(self.text.rows());
+        for row in 0..self.text.rows() {
+            for column in 0..self.text.columns() {
+                match self.text.get(column, row) {
+                    Some(cell) => {
+                        w.bool(true);
+                        w.u8(cell.c);
+                        w.u8(cell.fg);
+                        w.u8(cell.bg);
+                        w.bool(cell.underline);
+                    },
+                    None => w.bool(false),
+                }
+            }
+        }
+
+        w.u32(self.bitmaps.len() as u32);
+        for bitmap in self.bitmaps.iter() {
+            match bitmap {
+                Some(bitmap) => {
+                    w.bool(true);
+                    w.u32(bitmap.width());
+                    w.u32(bitmap.height());
+                    for c in bitmap.pixels() {
+                        w.0.extend_from_slice(&[c.r, c.g, c.b, c.a]);
+                    }
+                },
+                None => w.bool(false),
+            }
+        }
+        for n in [self.num_sprites, self.num_sprites_shown, self.current_sprite, self.current_bitmap] {
+            w.u8(n);
+        }
+        w.u32(self.sprites.len() as u32);
+        for sprite in self.sprites.iter() {
+            w.bytes(&sprite.frames);
+            w.u8(sprite.current_frame);
+            w.u16(sprite.pos_x as u16);
+            w.u16(sprite.pos_y as u16);
+            w.bool(sprite.visible);
+        }
+        w.0
+    }
+
+    /// Go back to the state saved by `snapshot`. The VDP is left alone if
+    /// the snapshot is damaged or of an unknown version.
+    pub fn restore(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut r = Reader { data, pos: 0 };
+        if r.take(4)? != MAGIC {
+            return Err("Not a VDP snapshot".to_string());
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(format!("Unsupported snapshot version {}", version));
+        }
+
+        let number = r.u8()?;
+        let mode = video_mode(number).ok_or(format!("Invalid mode {} in snapshot", number))?;
+        let palette = (0..r.u8()?).map(|_| Ok(Color::RGB(r.u8()?, r.u8()?, r.u8()?))).collect::<Result<Vec<_>, String>>()?;
+        if palette.is_empty() {
+            return Err("Empty palette in snapshot".to_string());
+        }
+        let screen = r.screen(mode.screen_width, mode.screen_height)?;
+        let front = r.screen(mode.screen_width, mode.screen_height)?;
+        if screen.pixels().iter().chain(front.pixels()).any(|&c| c as usize >= palette.len()) {
+            return Err("Pixel colour outside of the palette in snapshot".to_string());
+        }
+        let flip_pending = r.bool()?;
+
+        // Text and graphics foreground and background colours, then the
+        // two GCOL modes.
+        let colors = r.take(6)?;
+        if colors[..4].iter().any(|&c| c as usize >= palette.len()) {
+            return Err("Colour outside of the palette in snapshot".to_string());
+        }
+        let flags = (r.bool()?, r.bool()?, r.bool()?, r.bool()?, r.bool()?);
+
+        let mut cursor = Cursor::new(mode.screen_width as i32, mode.screen_height as i32, 8, 8);
+        cursor.position_x = r.i32()?;
+        cursor.position_y = r.i32()?;
+        cursor.font_width = r.i32()?;
+        cursor.font_height = r.i32()?;
+        cursor.paged_count = r.i32()?;
+        cursor.window_left = r.i32()?;
+        cursor.window_top = r.i32()?;
+        cursor.window_right = r.i32()?;
+        cursor.window_bottom = r.i32()?;
+        cursor.paged_mode = r.bool()?;
+        cursor.flags = r.u8()?;
+        cursor.pending_wrap = r.bool()?;
+        let screen_area = Rect::new(0, 0, mode.screen_width, mode.screen_height);
+        if cursor.window_left < 0 || cursor.window_top < 0 || cursor.window_left >= cursor.window_right || cursor.window_top >= cursor.window_bottom
+            || cursor.window_right > cursor.screen_width || cursor.window_bottom > cursor.screen_height {
+            return Err("Text window outside of the screen in snapshot".to_string());
+        }
+        let cursor_enabled = r.bool()?;
+        let cursor_style = match r.u8()? {
+            0 => CursorStyle::Block,
+            1 => CursorStyle::Underline,
+            _ => CursorStyle::Bar,
+        };
+        let cursor_start = r.u8()?;
+        let cursor_end = r.u8()?;
+        let cursor_blink = match r.u32()? {
+            0 => None,
+            ms => Some(Duration::from_millis(ms as u64)),
+        };
+
+        let points = [r.point()?, r.point()?, r.point()?, r.point()?, r.point()?];
+        let graph_window = r.rect()?;
+        if graph_window.intersection(screen_area) != Some(graph_window) {
+            return Err("Graphics window outside of the screen in snapshot".to_string());
+        }
+
+        let font = r.font()?;
+        if font.width() as i32 != cursor.font_width || font.height() as i32 != cursor.font_height {
+            return Err("Font does not match the cursor in snapshot".to_string());
+        }
+        let font_id = r.u16()?;
+        let mut fonts = HashMap::new();
+        for _ in 0..r.u32()? {
+            let id = r.u16()?;
+            fonts.insert(id, r.font()?);
+        }
+        if !fonts.contains_key(&SYSTEM_FONT) || !fonts.contains_key(&TERMINAL_FONT) {
+            return Err("Built in fonts missing from snapshot".to_string());
+        }
+        let mut buffers = HashMap::new();
+        for _ in 0..r.u32()? {
+            let id = r.u16()?;
+            buffers.insert(id, r.bytes()?.to_vec());
+        }
+
+        let (columns, rows) = (r.i32()?, r.i32()?);
+        if (columns, rows) != (cursor.screen_width / cursor.font_width, cursor.screen_height / cursor.font_height) {
+            return Err(format!("Text grid of {}x{} does not match the screen in snapshot", columns, rows));
+        }
+        if cursor.position_x < 0 || cursor.position_y < 0
+            || cursor.position_x >= columns * cursor.font_width || cursor.position_y >= rows * cursor.font_height {
+            return Err("Text cursor outside of the screen in snapshot".to_string());
+        }
+        let mut text = TextGrid::new(columns, rows);
+        for row in 0..text.rows() {
+            for column in 0..text.columns() {
+                if r.bool()? {
+                    let cell = Cell { c: r.u8()?, fg: r.u8()?, bg: r.u8()?, underline: r.bool()? };
+                    if cell.fg as usize >= palette.len() || cell.bg as usize >= palette.len() {
+                        return Err("Text colour outside of the palette in snapshot".to_string());
+                    }
+                    text.set(column, row, Some(cell));
+                }
+            }
+        }
+
+        let mut bitmaps = Vec::new();
+        for _ in 0..r.u32()? {
+            bitmaps.push(if r.bool()? {
+                let width = r.u32()?;
+                let height = r.u32()?;
+                let size = width.checked_mul(height).and_then(|n| n.checked_mul(4)).ok_or("Invalid bitmap size in snapshot")?;
+                let rgba = r.take(size as usize)?;
+                Some(Framebuffer::from_pixels(width, height, rgba.chunks(4).map(|c| Color::RGBA(c[0], c[1], c[2], c[3])).collect()))
+            } else {
+                None
+            });
+        }
+        let counts = r.take(4)?;
+        let mut sprites = Vec::new();
+        for _ in 0..r.u32()? {
+            sprites.push(Sprite {
+                frames: r.bytes()?.to_vec(),
+                current_frame: r.u8()?,
+                pos_x: r.u16()? as i16,
+                pos_y: r.u16()? as i16,
+                visible: r.bool()?,
+            });
+        }
+        if bitmaps.len() != self.bitmaps.len() || sprites.len() != self.sprites.len() {
+            return Err("Wrong number of bitmaps or sprites in snapshot".to_string());
+        }
+        if counts[2] as usize >= sprites.len() {
+            return Err("Invalid current sprite in snapshot".to_string());
+        }
+        for sprite in sprites.iter() {
+            if sprite.frames.iter().any(|&n| bitmaps[n as usize].is_none()) {
+                return Err("Sprite frame without a bitmap in snapshot".to_string());
+            }
+            if sprite.current_frame as usize >= sprite.frames.len() && (sprite.visible || !sprite.frames.is_empty()) {
+                return Err("Invalid sprite frame in snapshot".to_string());
+            }
+        }
+
+        self.change_mode(mode);
+        self.palette = palette;
+        self.screen = screen;
+        self.front = front;
+        self.flip_pending = flip_pending;
+        [self.foreground_color, self.background_color, self.graph_color, self.graph_bg_color, self.graph_mode, self.graph_bg_mode] =
+            [colors[0], colors[1], colors[2], colors[3], colors[4], colors[5]];
+        (self.logical_coords, self.terminal_mode, self.text_at_graphics_cursor, self.terminal_underline, self.terminal_reverse) = flags;
+        self.cursor = cursor;
+        self.cursor_enabled = cursor_enabled;
+        self.cursor_style = cursor_style;
+        self.cursor_start = cursor_start;
+        self.cursor_end = cursor_end;
+        self.cursor_blink = cursor_blink;
+        [self.graph_cursor, self.p1, self.p2, self.p3, self.graph_origin] = points;
+        self.graph_window = graph_window;
+        self.font = font;
+        self.font_id = font_id;
+        self.fonts = fonts;
+        self.buffers = buffers;
+        self.glyph_cache.clear();
+        self.text = text;
+        self.bitmaps = bitmaps;
+        [self.num_sprites, self.num_sprites_shown, self.current_sprite, self.current_bitmap] = [counts[0], counts[1], counts[2], counts[3]];
+        self.sprites = sprites;
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use sdl2::rect::Rect;
+
+/// A character printed at the text cursor, with the colours and
+/// attributes it was printed in.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Cell {
+    pub c: u8,
+    pub fg: u8,
+    pub bg: u8,
+    pub underline: bool,
+}
+
+/// What the text screen holds in characters rather than pixels, kept
+/// next to the framebuffer so text can be read back and redrawn exactly.
+/// Cells that were cleared or never printed to are None.
+pub struct TextGrid {
+    columns: i32,
+    rows: i32,
+    cells: Vec<Option<Cell>>,
+}
+
+impl TextGrid {
+    pub fn new(columns: i32, rows: i32) -> TextGrid {
+        TextGrid { columns, rows, cells: vec![None; (columns.max(0) * rows.max(0)) as usize] }
+    }
+
+    pub fn columns(&self) -> i32 {
+        self.columns
+    }
+
+    pub fn rows(&self) -> i32 {
+        self.rows
+    }
+
+    fn index(&self, column: i32, row: i32) -> Option<usize> {
+        if column >= 0 && row >= 0 && column < self.columns && row < self.rows {
+            Some((row * self.columns + column) as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn get(&self, column: i32, row: i32) -> Option<Cell> {
+        self.index(column, row).and_then(|i| self.cells[i])
+    }
+
+    pub fn set(&mut self, column: i32, row: i32, cell: Option<Cell>) {
+        if let Some(i) = self.index(column, row) {
+            self.cells[i] = cell;
+        }
+    }
+
+    /// Clear the cells of `area`, given in cells.
+    pub fn clear(&mut self, area: Rect) {
+        for row in area.y()..area.bottom() {
+            for column in area.x()..area.right() {
+                self.set(column, row, None);
+            }
+        }
+    }
+
+    /// Move the cells of `area` by n cells in a VDU 23,7 direction: right
+    /// (0), left (1), down (2) or up (3). Cells moved in are cleared.
+    pub fn scroll(&mut self, area: Rect, direction: u8, n: i32) {
+        let (dx, dy) = match direction {
+            0 => (n, 0),
+            1 => (-n, 0),
+            2 => (0, n),
+            3 => (0, -n),
+            _ => return,
+        };
+        let source: Vec<Option<Cell>> = self.cells.clone();
+        let at = |column: i32, row: i32| if area.contains_point((column, row)) {
+            self.index(column, row).and_then(|i| source[i])
+        } else {
+            None
+        };
+        let moved: Vec<(i32, i32, Option<Cell>)> = (area.y()..area.bottom())
+            .flat_map(|row| (area.x()..area.right()).map(move |column| (column, row)))
+            .map(|(column, row)| (column, row, at(column - dx, row - dy)))
+            .collect();
+        for (column, row, cell) in moved {
+            self.set(column, row, cell);
+        }
+    }
+
+    /// The grid with another size, keeping the cells that still fit.
+    pub fn resized(&self, columns: i32, rows: i32) -> TextGrid {
+        let mut grid = TextGrid::new(columns, rows);
+        for row in 0..rows.min(self.rows) {
+            for column in 0..columns.min(self.columns) {
+                grid.set(column, row, self.get(column, row));
+            }
+        }
+        grid
+    }
+}
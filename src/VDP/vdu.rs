@@ -0,0 +1,100 @@
+use std::collections::VecDeque;
+
+// Number of bytes making up the complete command at the start of `buf`,
+// including the command byte itself. Returns None as long as not enough
+// bytes have arrived to tell, so the caller can wait for more data
+// instead of blocking in the middle of a command.
+pub fn command_length(buf: &VecDeque<u8>, terminal_mode: bool) -> Option<usize> {
+    let at = |i: usize| buf.get(i).copied();
+    let word = |i: usize| Some(i16::from_le_bytes([at(i)?, at(i + 1)?]));
+
+    if terminal_mode {
+        return match at(0)? {
+            0x1B => escape_sequence_length(buf),
+            _ => Some(1),
+        };
+    }
+
+    match at(0)? {
+        0x11 => Some(2),
+        0x12 => Some(3),
+        0x13 => Some(6),
+        0x16 => Some(2),
+        0x17 => match at(1)? {
+            0x00 => match at(2)? {
+                0x0A | 0x0B | 0x80 | 0x81 | 0xC0 => Some(4),
+                0x83 | 0x84 => Some(7),
+                0x85 => match at(4)? {
+                    0 => Some(10),
+                    4 => if at(5)? == 8 { Some(8) } else { Some(6) },
+                    5 => match at(5)? {
+                        0 => Some(12 + u32::from_le_bytes([at(9)?, at(10)?, at(11)?, 0]) as usize),
+                        2 => Some(11),
+                        3 => Some(12),
+                        4 => Some(8),
+                        _ => Some(6),
+                    },
+                    6 => if at(5)? == 1 { Some(13) } else { Some(6) },
+                    7 => if at(5)? == 1 { Some(10 + 4 * at(6)? as usize) } else { Some(6) },
+                    _ => Some(5),
+                },
+                0x87 => if at(3)? == 0 { Some(4) } else { Some(10) },
+                0x88 => Some(6),
+                0x95 => match at(3)? {
+                    0 => Some(7),
+                    1 => Some(10),
+                    _ => Some(6),
+                },
+                0xA0 => match at(5)? {
+                    0 => Some(8 + u16::from_le_bytes([at(6)?, at(7)?]) as usize),
+                    _ => Some(6),
+                },
+                _ => Some(3),
+            },
+            0x01 => Some(3),
+            0x07 => Some(5),
+            0x10 => Some(4),
+            0x1B => match at(2)? {
+                0 | 4 | 6 | 7 | 10 => Some(4),
+                1 => {
+                    let w = word(3)?;
+                    let h = word(5)?;
+                    if w > 0 && h > 0 {
+                        // Up to 0x7FFF x 0x7FFF pixels of 4 bytes.
+                        let size = (w as usize).checked_mul(h as usize).and_then(|n| n.checked_mul(4));
+                        Some(size.and_then(|n| n.checked_add(7)).unwrap_or(usize::MAX))
+                    } else {
+                        Some(7)
+                    }
+                },
+                2 => {
+                    let w = word(3)?;
+                    let h = word(5)?;
+                    if w > 0 && h > 0 { Some(11) } else { Some(7) }
+                },
+                3 | 13 | 14 => Some(7),
+                _ => Some(3),
+            },
+            n if n >= 32 => Some(10),
+            _ => Some(2),
+        },
+        0x18 => Some(9),
+        0x19 => Some(6),
+        0x1B => Some(2),
+        0x1C => Some(5),
+        0x1D => Some(5),
+        0x1F => Some(3),
+        _ => Some(1),
+    }
+}
+
+// ESC followed by '[' takes digits and ';' until any other byte ends it,
+// ESC followed by anything else is a two byte sequence.
+fn escape_sequence_length(buf: &VecDeque<u8>) -> Option<usize> {
+    if *buf.get(1)? != b'[' {
+        return Some(2);
+    }
+    buf.iter().skip(2)
+        .position(|c| !(c.is_ascii_digit() || *c == b';'))
+        .map(|i| i + 3)
+}
@@ -1 +1,2 @@
 pub mod VDP;
+pub mod transport;
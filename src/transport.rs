@@ -0,0 +1,215 @@
+//! Connects the VDP to an eZ80 host outside of this process.
+//!
+//! The VDP talks to its host through a pair of `std::sync::mpsc` channels.
+//! A `Link` bridges those channels to a TCP socket, a serial device or a
+//! pseudo terminal, so the VDP can run standalone against an external CPU
+//! emulator or a real Agon over its UART.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use log::{info, warn};
+
+pub enum Transport {
+    /// Connect to a host listening on the given TCP address.
+    TcpConnect(String),
+    /// Listen on the given TCP address and serve the first host to connect.
+    TcpListen(String),
+    /// Serial device path and baud rate, e.g. "/dev/ttyUSB0" at 1152000.
+    #[cfg(unix)]
+    Serial(String, u32),
+    /// A new pseudo terminal; the host opens the slave side named by
+    /// `Link::name`.
+    #[cfg(unix)]
+    Pty,
+}
+
+/// The VDP end of a connection to the host. `tx` and `rx` are meant to be
+/// passed to `VDP::new`; bytes are pumped between them and the underlying
+/// stream by two background threads.
+pub struct Link {
+    pub tx: Sender<u8>,
+    pub rx: Receiver<u8>,
+    /// Human readable name of the other end, e.g. the pty slave path.
+    pub name: String,
+}
+
+impl Link {
+    pub fn open(transport: &Transport) -> io::Result<Link> {
+        match transport {
+            Transport::TcpConnect(address) => {
+                let stream = TcpStream::connect(address)?;
+                stream.set_nodelay(true)?;
+                let name = stream.peer_addr()?.to_string();
+                Ok(Self::bridge(stream.try_clone()?, stream, name))
+            },
+            Transport::TcpListen(address) => {
+                let listener = TcpListener::bind(address)?;
+                info!("Waiting for host on {}", listener.local_addr()?);
+                let (stream, peer) = listener.accept()?;
+                stream.set_nodelay(true)?;
+                Ok(Self::bridge(stream.try_clone()?, stream, peer.to_string()))
+            },
+            #[cfg(unix)]
+            Transport::Serial(path, baud) => {
+                let file = unix::open_serial(path, *baud)?;
+                Ok(Self::bridge(file.try_clone()?, file, path.clone()))
+            },
+            #[cfg(unix)]
+            Transport::Pty => {
+                let (pty, name) = unix::open_pty()?;
+                Ok(Self::bridge(pty.try_clone()?, pty, name))
+            },
+        }
+    }
+
+    /// Bridge any byte stream, given as separate reading and writing halves.
+    pub fn bridge<R, W>(mut reader: R, mut writer: W, name: String) -> Link
+    where R: Read + Send + 'static,
+          W: Write + Send + 'static {
+        let (tx_to_vdp, rx_from_host): (Sender<u8>, Receiver<u8>) = mpsc::channel();
+        let (tx_to_host, rx_from_vdp): (Sender<u8>, Receiver<u8>) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut buf = [0u8; 1024];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) => { info!("Host closed the connection"); break; },
+                    Ok(n) => {
+                        if buf[..n].iter().any(|b| tx_to_vdp.send(*b).is_err()) {
+                            break;
+                        }
+                    },
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                    Err(e) => { warn!("Reading from host failed: {}", e); break; },
+                }
+            }
+        });
+
+        thread::spawn(move || {
+            // Block for the first byte, then send whatever else is queued
+            // in one go.
+            while let Ok(b) = rx_from_vdp.recv() {
+                let mut packet = vec![b];
+                packet.extend(rx_from_vdp.try_iter());
+                if let Err(e) = writer.write_all(&packet).and_then(|_| writer.flush()) {
+                    warn!("Writing to host failed: {}", e);
+                    break;
+                }
+            }
+        });
+
+        Link { tx: tx_to_host, rx: rx_from_host, name }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::ffi::{CStr, CString};
+    use std::fs::File;
+    use std::io::{self, Read, Write};
+    use std::os::unix::io::{FromRawFd, RawFd};
+
+    fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+        if result < 0 { Err(io::Error::last_os_error()) } else { Ok(result) }
+    }
+
+    fn make_raw(fd: RawFd, baud: Option<libc::speed_t>) -> io::Result<()> {
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            check(libc::tcgetattr(fd, &mut termios))?;
+            libc::cfmakeraw(&mut termios);
+            termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+            if let Some(speed) = baud {
+                check(libc::cfsetispeed(&mut termios, speed))?;
+                check(libc::cfsetospeed(&mut termios, speed))?;
+            }
+            check(libc::tcsetattr(fd, libc::TCSANOW, &termios))?;
+        }
+        Ok(())
+    }
+
+    fn baud_constant(baud: u32) -> io::Result<libc::speed_t> {
+        Ok(match baud {
+            9600 => libc::B9600,
+            19200 => libc::B19200,
+            38400 => libc::B38400,
+            57600 => libc::B57600,
+            115200 => libc::B115200,
+            230400 => libc::B230400,
+            #[cfg(target_os = "linux")]
+            460800 => libc::B460800,
+            #[cfg(target_os = "linux")]
+            500000 => libc::B500000,
+            #[cfg(target_os = "linux")]
+            921600 => libc::B921600,
+            #[cfg(target_os = "linux")]
+            1000000 => libc::B1000000,
+            #[cfg(target_os = "linux")]
+            1152000 => libc::B1152000,
+            #[cfg(target_os = "linux")]
+            1500000 => libc::B1500000,
+            #[cfg(target_os = "linux")]
+            2000000 => libc::B2000000,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unsupported baud rate {}", baud))),
+        })
+    }
+
+    pub fn open_serial(path: &str, baud: u32) -> io::Result<File> {
+        let speed = baud_constant(baud)?;
+        let c_path = CString::new(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let fd = check(unsafe { libc::open(c_path.as_ptr(), libc::O_RDWR | libc::O_NOCTTY) })?;
+        let file = unsafe { File::from_raw_fd(fd) };
+        make_raw(fd, Some(speed))?;
+        Ok(file)
+    }
+
+    /// Master side of a pseudo terminal. We keep the slave side open as
+    /// well, otherwise reading the master fails with EIO until the host
+    /// has opened it, and again after every time the host closes it.
+    pub struct Pty {
+        master: File,
+        _slave: File,
+    }
+
+    impl Pty {
+        pub fn try_clone(&self) -> io::Result<Pty> {
+            Ok(Pty { master: self.master.try_clone()?, _slave: self._slave.try_clone()? })
+        }
+    }
+
+    impl Read for Pty {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.master.read(buf)
+        }
+    }
+
+    impl Write for Pty {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.master.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.master.flush()
+        }
+    }
+
+    pub fn open_pty() -> io::Result<(Pty, String)> {
+        let fd = check(unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) })?;
+        let file = unsafe { File::from_raw_fd(fd) };
+        check(unsafe { libc::grantpt(fd) })?;
+        check(unsafe { libc::unlockpt(fd) })?;
+        let name = unsafe {
+            let p = libc::ptsname(fd);
+            if p.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            CStr::from_ptr(p).to_string_lossy().into_owned()
+        };
+        make_raw(fd, None)?;
+        let slave = File::options().read(true).write(true).open(&name)?;
+        Ok((Pty { master: file, _slave: slave }, name))
+    }
+}
@@ -0,0 +1,297 @@
+//! Sound of a headless VDP, mixed with `render_audio`.
+
+mod common;
+
+use std::f32::consts::TAU;
+
+use common::Host;
+
+impl Host<'_> {
+    // Play a note and return the reply: channel and whether it was started.
+    fn play(&mut self, channel: u8, volume: u8, frequency: u16, duration: u16) -> Vec<u8> {
+        let [f0, f1] = frequency.to_le_bytes();
+        let [d0, d1] = duration.to_le_bytes();
+        self.rx.try_iter().for_each(drop);
+        self.send(&[23, 0, 0x85, channel, 0, volume, f0, f1, d0, d1]);
+        let reply: Vec<u8> = self.rx.try_iter().collect();
+        assert_eq!(reply[0], 0x85, "not an audio packet");
+        reply[2..].to_vec()
+    }
+
+    fn status(&mut self, channel: u8) -> u8 {
+        self.rx.try_iter().for_each(drop);
+        self.send(&[23, 0, 0x85, channel, 1]);
+        let reply: Vec<u8> = self.rx.try_iter().collect();
+        assert_eq!(reply[2], channel);
+        reply[3]
+    }
+
+    fn render(&mut self, n: usize) -> Vec<f32> {
+        let mut out = vec![0.0; n];
+        self.vdp.render_audio(&mut out);
+        out
+    }
+}
+
+// Full volume.
+const PEAK: f32 = 0.3;
+
+// 441 Hz is a period of 100 samples.
+fn assert_waveform(waveform: &[u8], expected: impl Fn(f32) -> f32) {
+    let mut host = Host::new();
+    host.send(&[&[23, 0, 0x85, 1, 4][..], waveform].concat());
+    assert_eq!(host.play(1, 255, 441, 1000), [1, 1]);
+    for (i, sample) in host.render(200).iter().enumerate() {
+        let want = expected((i % 100) as f32 / 100.0) * PEAK;
+        assert!((sample - want).abs() < 0.001, "waveform {}: sample {} is {}, expected {}", waveform[0], i, sample, want);
+    }
+}
+
+#[test]
+fn square() {
+    assert_waveform(&[0], |phase| if phase < 0.5 { 1.0 } else { -1.0 });
+}
+
+#[test]
+fn triangle() {
+    assert_waveform(&[1], |phase| if phase < 0.5 { phase * 4.0 - 1.0 } else { 3.0 - phase * 4.0 });
+}
+
+#[test]
+fn sawtooth() {
+    assert_waveform(&[2], |phase| phase * 2.0 - 1.0);
+}
+
+#[test]
+fn sine() {
+    assert_waveform(&[3], |phase| (phase * TAU).sin());
+}
+
+#[test]
+fn user_sample() {
+    let mut host = Host::new();
+    host.send(&[23, 0, 0xA0, 1, 0, 0, 4, 0, 127, 0, 0x80, 0]);
+    host.send(&[23, 0, 0x85, 0, 4, 8, 1, 0]);
+    host.play(0, 255, 441, 1000);
+    let samples = host.render(100);
+    for (i, want) in [(0, 127.0 / 128.0), (30, 0.0), (60, -1.0), (99, 0.0)] {
+        assert!((samples[i] - want * PEAK).abs() < 0.001, "sample {} is {}", i, samples[i]);
+    }
+}
+
+#[test]
+fn noise() {
+    let mut host = Host::new();
+    host.send(&[23, 0, 0x85, 2, 4, 4]);
+    host.play(2, 255, 441, 1000);
+    let samples = host.render(1000);
+    assert!(samples.iter().all(|s| s.abs() <= PEAK));
+    let changes = samples.windows(2).filter(|w| w[0] != w[1]).count();
+    assert!(changes > 900, "only {} changes", changes);
+}
+
+#[test]
+fn note_ends_after_its_duration() {
+    let mut host = Host::new();
+    assert_eq!(host.play(0, 255, 441, 10), [0, 1]);
+    assert_eq!(host.play(0, 255, 441, 10), [0, 0], "channel is busy");
+    let samples = host.render(1000);
+    assert!(samples[..441].iter().all(|s| *s != 0.0));
+    assert!(samples[441..].iter().all(|s| *s == 0.0));
+    assert_eq!(host.play(0, 255, 441, 10), [0, 1]);
+}
+
+#[test]
+fn volume_envelope() {
+    let mut host = Host::new();
+    // Attack, decay and release of 10 ms each, sustain at half volume.
+    host.send(&[23, 0, 0x85, 0, 6, 1, 10, 0, 10, 0, 127, 10, 0]);
+    host.play(0, 255, 441, 50);
+    let samples = host.render(3000);
+    let level = |i: usize| samples[i].abs() / PEAK;
+    for (i, want) in [(0, 0.0), (220, 0.5), (440, 1.0), (661, 0.75), (1000, 0.5), (2204, 0.5), (2425, 0.25)] {
+        assert!((level(i) - want).abs() < 0.01, "level at {} is {}, expected {}", i, level(i), want);
+    }
+    assert!(samples[2646..].iter().all(|s| *s == 0.0));
+}
+
+#[test]
+fn longest_volume_envelope() {
+    let mut host = Host::new();
+    // Attack and release of 65535 ms, far more samples than 65535 * 44100
+    // fits in an i32.
+    host.send(&[23, 0, 0x85, 0, 6, 1, 0xFF, 0xFF, 0, 0, 255, 0xFF, 0xFF]);
+    host.play(0, 255, 441, 1000);
+    let samples = host.render(44100);
+    let attack = 65535.0 * 44.1;
+    for i in [1000, 22050, 44099] {
+        let level = samples[i].abs() / PEAK;
+        assert!((level - i as f32 / attack).abs() < 0.0001, "level at {} is {}", i, level);
+    }
+    host.render(44100);
+    assert_eq!(host.status(0), 2, "still releasing");
+}
+
+#[test]
+fn channel_is_busy_until_released() {
+    let mut host = Host::new();
+    host.send(&[23, 0, 0x85, 0, 6, 1, 0, 0, 0, 0, 255, 100, 0]);
+    host.play(0, 255, 441, 10);
+    host.render(1000);
+    assert_eq!(host.play(0, 255, 441, 10), [0, 0]);
+    host.render(4000);
+    assert_eq!(host.play(0, 255, 441, 10), [0, 1]);
+}
+
+// Check the frequency of a square wave in every 10 ms from the number of
+// sign changes, twice per cycle give or take one.
+fn assert_frequencies(samples: &[f32], frequencies: &[usize]) {
+    let changes: Vec<usize> = samples.chunks(441).map(|chunk| chunk.windows(2).filter(|w| (w[0] < 0.0) != (w[1] < 0.0)).count()).collect();
+    for (i, (n, frequency)) in changes.iter().zip(frequencies).enumerate() {
+        assert!(n.abs_diff(frequency / 50) <= 1, "{} sign changes in period {}, expected {} Hz", n, i, frequency);
+    }
+}
+
+#[test]
+fn frequency_sweep() {
+    let mut host = Host::new();
+    // Two steps of 10 ms, each raising the pitch by 441 Hz.
+    host.send(&[23, 0, 0x85, 0, 7, 1, 1, 0, 10, 0, 0xB9, 0x01, 2, 0]);
+    host.play(0, 255, 441, 1000);
+    assert_frequencies(&host.render(441 * 4), &[441, 882, 1323, 1323]);
+}
+
+#[test]
+fn longest_frequency_step() {
+    let mut host = Host::new();
+    // Steps of 60000 ms raising the pitch by 441 Hz, none taken yet.
+    host.send(&[23, 0, 0x85, 0, 7, 1, 1, 0, 0x60, 0xEA, 0xB9, 0x01, 1, 0]);
+    host.play(0, 255, 441, 1000);
+    assert_frequencies(&host.render(441 * 4), &[441, 441, 441, 441]);
+}
+
+#[test]
+fn vibrato() {
+    let mut host = Host::new();
+    // Up and down by 441 Hz every 10 ms, repeating.
+    host.send(&[23, 0, 0x85, 0, 7, 1, 2, 1, 10, 0, 0xB9, 0x01, 1, 0, 0x47, 0xFE, 1, 0]);
+    host.play(0, 255, 441, 1000);
+    assert_frequencies(&host.render(441 * 5), &[441, 882, 441, 882, 441]);
+}
+
+#[test]
+fn channels_can_be_enabled_up_to_32() {
+    let mut host = Host::new();
+    assert_eq!(host.play(3, 255, 441, 10), [3, 0]);
+    assert_eq!(host.status(3), 255);
+    for channel in 3..32 {
+        host.send(&[23, 0, 0x85, channel, 8]);
+    }
+    host.send(&[23, 0, 0x85, 32, 8]);
+    assert_eq!(host.status(31), 0);
+    assert_eq!(host.status(32), 255);
+    for channel in 0..32 {
+        assert_eq!(host.play(channel, 8, 441, 10), [channel, 1]);
+    }
+    assert_eq!(host.play(32, 255, 441, 10), [32, 0]);
+    let samples = host.render(10);
+    assert!((samples[0] - 32.0 * 8.0 / 255.0 * PEAK).abs() < 0.001);
+}
+
+#[test]
+fn channel_status() {
+    let mut host = Host::new();
+    host.send(&[23, 0, 0x85, 1, 6, 1, 0, 0, 0, 0, 255, 10, 0]);
+    assert_eq!(host.status(1), 0);
+    host.play(1, 255, 441, 10);
+    assert_eq!(host.status(1), 1);
+    host.render(500);
+    assert_eq!(host.status(1), 2);
+    host.render(500);
+    assert_eq!(host.status(1), 0);
+}
+
+#[test]
+fn disable_and_reset() {
+    let mut host = Host::new();
+    host.play(0, 255, 441, 1000);
+    host.send(&[23, 0, 0x85, 0, 9]);
+    assert!(host.render(100).iter().all(|s| *s == 0.0));
+    assert_eq!(host.play(0, 255, 441, 1000), [0, 0]);
+
+    host.send(&[23, 0, 0x85, 1, 4, 3]);
+    host.play(1, 255, 441, 1000);
+    host.send(&[23, 0, 0x85, 1, 10]);
+    assert!(host.render(100).iter().all(|s| *s == 0.0));
+    assert_eq!(host.play(1, 255, 441, 1000), [1, 1]);
+    assert_eq!(host.render(1)[0], PEAK, "reset to a square wave");
+}
+
+// Upload signed 8 bit samples as sample -1 at 44.1 kHz.
+fn upload(host: &mut Host, data: &[i8]) {
+    let [l0, l1, l2, _] = (data.len() as u32).to_le_bytes();
+    host.send(&[23, 0, 0x85, 0xFF, 5, 0, 1, 0x44, 0xAC, l0, l1, l2]);
+    host.send(&data.iter().map(|s| *s as u8).collect::<Vec<u8>>());
+}
+
+fn ramp() -> Vec<i8> {
+    (0..100).map(|i| i as i8 - 50).collect()
+}
+
+#[test]
+fn sample_plays_once() {
+    let mut host = Host::new();
+    upload(&mut host, &ramp());
+    host.send(&[23, 0, 0x85, 0, 4, 0xFF]);
+    assert_eq!(host.play(0, 255, 1000, 65535), [0, 1]);
+    let samples = host.render(200);
+    for (i, want) in ramp().iter().enumerate() {
+        assert!((samples[i] - *want as f32 / 128.0 * PEAK).abs() < 0.001, "sample {} is {}", i, samples[i]);
+    }
+    assert!(samples[100..].iter().all(|s| *s == 0.0));
+    assert_eq!(host.status(0), 0, "the note ends with the sample");
+}
+
+#[test]
+fn sample_pitch_and_loop() {
+    let mut host = Host::new();
+    upload(&mut host, &ramp());
+    // Twice the base frequency plays every other sample, the last 20
+    // samples loop.
+    host.send(&[23, 0, 0x85, 0xFF, 5, 4, 0xB9, 0x01]);
+    host.send(&[23, 0, 0x85, 0xFF, 5, 3, 80, 0, 0, 20, 0, 0]);
+    host.send(&[23, 0, 0x85, 1, 4, 0xFF]);
+    host.play(1, 255, 882, 65535);
+    let samples = host.render(1000);
+    let at = |i: usize| (samples[i] / PEAK * 128.0).round() as i32 + 50;
+    assert_eq!((0..5).map(at).collect::<Vec<_>>(), [0, 2, 4, 6, 8]);
+    assert_eq!((50..55).map(at).collect::<Vec<_>>(), [80, 82, 84, 86, 88]);
+    assert_eq!((995..1000).map(at).collect::<Vec<_>>(), [90, 92, 94, 96, 98]);
+    assert_eq!(host.status(1), 1);
+}
+
+#[test]
+fn sample_loop_in_the_middle() {
+    let mut host = Host::new();
+    upload(&mut host, &ramp());
+    // Samples 30 to 39 repeat, the rest of the sample is never reached.
+    host.send(&[23, 0, 0x85, 0xFF, 5, 3, 30, 0, 0, 10, 0, 0]);
+    host.send(&[23, 0, 0x85, 0, 4, 0xFF]);
+    host.play(0, 255, 1000, 65535);
+    let samples = host.render(1000);
+    let at = |i: usize| (samples[i] / PEAK * 128.0).round() as i32 + 50;
+    assert_eq!((25..45).map(at).collect::<Vec<_>>(), [25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 30, 31, 32, 33, 34]);
+    assert!((40..1000).all(|i| (30..40).contains(&at(i))), "plays past the loop");
+    assert_eq!((990..1000).map(at).collect::<Vec<_>>(), (30..40).collect::<Vec<_>>());
+}
+
+#[test]
+fn sample_from_buffer() {
+    let mut host = Host::new();
+    host.send(&[23, 0, 0xA0, 3, 0, 0, 3, 0, 0x80, 0xC0, 0x40]);
+    host.send(&[23, 0, 0x85, 0x80, 5, 2, 0, 0x44, 0xAC, 3, 0]);
+    host.send(&[23, 0, 0x85, 2, 4, 0x80]);
+    host.play(2, 255, 100, 1000);
+    let samples = host.render(4);
+    assert_eq!(samples, [0.0, 0.5 * PEAK, -0.5 * PEAK, 0.0]);
+}
@@ -0,0 +1,31 @@
+//! A headless VDP with the channels of the eZ80 side, shared by the
+//! integration tests and benchmarks.
+
+// Every test binary uses only part of this.
+#![allow(dead_code)]
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use agon_light_vdp::VDP::VDP;
+
+pub struct Host<'a> {
+    pub vdp: VDP<'a>,
+    pub tx: Sender<u8>,
+    pub rx: Receiver<u8>,
+}
+
+impl Host<'_> {
+    pub fn new() -> Self {
+        let (tx_vdp, rx) = channel();
+        let (tx, rx_vdp) = channel();
+        Host { vdp: VDP::headless(tx_vdp, rx_vdp, Default::default()), tx, rx }
+    }
+
+    /// Send bytes as the eZ80 would and let the VDP process all of them.
+    pub fn send(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.tx.send(*b).unwrap();
+        }
+        while self.vdp.do_comms() {}
+    }
+}
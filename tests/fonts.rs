@@ -0,0 +1,93 @@
+//! Parsing of the font formats accepted by `Font::load` and the font
+//! buffer command.
+
+use agon_light_vdp::VDP::Font;
+
+// The rows of character c as strings of '#' and '.'.
+fn rows(font: &Font, c: u8) -> Vec<String> {
+    (0..font.height() as i32)
+        .map(|y| (0..font.width() as i32).map(|x| if font.pixel(c, x, y) { '#' } else { '.' }).collect())
+        .collect()
+}
+
+#[test]
+fn psf1() {
+    let mut data = vec![0x36, 0x04, 0x00, 4];
+    data.resize(4 + 256 * 4, 0);
+    data[4 + b'A' as usize * 4..][..4].copy_from_slice(&[0x18, 0x24, 0x7E, 0x42]);
+    let font = Font::parse(0, 0, &data).unwrap();
+    assert_eq!((font.width(), font.height()), (8, 4));
+    assert_eq!(rows(&font, b'A'), ["...##...", "..#..#..", ".######.", ".#....#."]);
+}
+
+#[test]
+fn psf2() {
+    let mut data = vec![0x72, 0xb5, 0x4a, 0x86];
+    for word in [0, 32, 0, 256, 4, 2, 10] {
+        data.extend_from_slice(&(word as u32).to_le_bytes());
+    }
+    data.resize(32 + 256 * 4, 0);
+    data[32 + b'x' as usize * 4..][..4].copy_from_slice(&[0x80, 0x40, 0x7F, 0xC0]);
+    let font = Font::parse(0, 0, &data).unwrap();
+    assert_eq!((font.width(), font.height()), (10, 2));
+    assert_eq!(rows(&font, b'x'), ["#........#", ".#########"]);
+}
+
+#[test]
+fn bad_psf() {
+    let psf2 = |glyphs: u32, glyph_size: u32, height: u32, width: u32, len: usize| {
+        let mut data = vec![0x72, 0xb5, 0x4a, 0x86];
+        for word in [0, 32, 0, glyphs, glyph_size, height, width] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data.resize(32 + len, 0);
+        Font::parse(0, 0, &data)
+    };
+    assert!(psf2(256, 4, 2, 10, 1024).is_ok());
+    assert!(psf2(256, 4, 2, 10, 1023).is_err(), "truncated");
+    assert!(psf2(256, 0x0FFFFFFF, 0x0FFFFFFF, 0x0FFFFFFF, 1024).is_err(), "too big");
+    assert!(psf2(256, 256 * 256, 256, 256 * 8, 1024).is_err(), "too big");
+    assert!(psf2(0x10000, 0x10000, 0x8000, 16, 1024).is_err(), "overflowing size");
+    assert!(psf2(256, 3, 2, 10, 1024).is_err(), "glyph size does not match");
+    assert!(Font::parse(0, 0, &[0x36, 0x04, 0x01, 4, 0, 0]).is_err(), "truncated");
+}
+
+#[test]
+fn bdf() {
+    let text = "STARTFONT 2.1
+FONTBOUNDINGBOX 4 5 0 -1
+STARTCHAR A
+ENCODING 65
+BBX 3 3 1 0
+BITMAP
+40
+A0
+E0
+ENDCHAR
+STARTCHAR g
+ENCODING 103
+BBX 2 2 0 -1
+BITMAP
+C0
+40
+ENDCHAR
+ENDFONT
+";
+    let font = Font::parse(0, 0, text.as_bytes()).unwrap();
+    assert_eq!((font.width(), font.height()), (4, 5));
+    assert_eq!(rows(&font, b'A'), ["....", "..#.", ".#.#", ".###", "...."]);
+    assert_eq!(rows(&font, b'g'), ["....", "....", "....", "##..", ".#.."]);
+
+    let long_row = text.replace("\nC0\n", "\n80000000000000000\n");
+    assert!(Font::parse(0, 0, long_row.as_bytes()).is_err());
+    let huge = text.replace("FONTBOUNDINGBOX 4 5", "FONTBOUNDINGBOX 100000 100000");
+    assert!(Font::parse(0, 0, huge.as_bytes()).is_err());
+}
+
+#[test]
+fn raw() {
+    let font = Font::parse(12, 1, &[0xFF, 0xF0, 0x80, 0x10]).unwrap();
+    assert_eq!(rows(&font, 0), ["############"]);
+    assert_eq!(rows(&font, 1), ["#..........#"]);
+    assert!(Font::parse(0, 8, &[]).is_err());
+}
@@ -0,0 +1,167 @@
+//! Golden image tests for VDU command streams.
+//!
+//! Every `tests/vdu/<name>.vdu` file is a recorded byte stream as sent by
+//! the eZ80. It is played through a headless VDP and the final frame is
+//! compared with `tests/golden/<name>.png`. On a mismatch the actual frame
+//! is written next to the test binaries (see the failure message).
+//!
+//! After an intended change of the output regenerate the golden images
+//! with
+//!
+//!     UPDATE_GOLDEN=1 cargo test --test golden
+//!
+//! and review the new PNGs before committing them.
+
+mod common;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use common::Host;
+
+struct Image {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+fn play(stream: &[u8]) -> Image {
+    let mut host = Host::new();
+    host.send(stream);
+    while host.vdp.waiting_for_vsync() {
+        host.vdp.vsync();
+        while host.vdp.do_comms() {}
+    }
+    let frame = host.vdp.frame();
+    Image {
+        width: frame.width(),
+        height: frame.height(),
+        rgba: frame.pixels().iter().flat_map(|c| [c.r, c.g, c.b, 0xFF]).collect(),
+    }
+}
+
+fn read_png(path: &Path) -> Image {
+    let decoder = png::Decoder::new(File::open(path).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    let mut rgba = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut rgba).unwrap();
+    assert_eq!(info.color_type, png::ColorType::Rgba, "{} is not an RGBA image", path.display());
+    rgba.truncate(info.buffer_size());
+    Image { width: info.width, height: info.height, rgba }
+}
+
+fn write_png(path: &Path, image: &Image) {
+    let file = BufWriter::new(File::create(path).unwrap());
+    let mut encoder = png::Encoder::new(file, image.width, image.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header().unwrap().write_image_data(&image.rgba).unwrap();
+}
+
+fn check_golden(name: &str) {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let stream = std::fs::read(root.join("vdu").join(format!("{name}.vdu"))).unwrap();
+    let golden_path = root.join("golden").join(format!("{name}.png"));
+    let actual = play(&stream);
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        write_png(&golden_path, &actual);
+        return;
+    }
+
+    let golden = read_png(&golden_path);
+    let same_size = golden.width == actual.width && golden.height == actual.height;
+    let differences = golden.rgba.chunks(4).zip(actual.rgba.chunks(4)).filter(|(g, a)| g != a).count();
+    if !same_size || differences > 0 {
+        let actual_path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}.actual.png"));
+        write_png(&actual_path, &actual);
+        panic!("{name}: frame differs from golden image ({}x{} vs {}x{}, {} pixels differ), actual frame written to {}",
+               actual.width, actual.height, golden.width, golden.height, differences, actual_path.display());
+    }
+}
+
+#[test]
+fn text() {
+    check_golden("text");
+}
+
+#[test]
+fn graphics() {
+    check_golden("graphics");
+}
+
+#[test]
+fn scroll() {
+    check_golden("scroll");
+}
+
+#[test]
+fn sprites() {
+    check_golden("sprites");
+}
+
+#[test]
+fn shapes() {
+    check_golden("shapes");
+}
+
+#[test]
+fn plot_actions() {
+    check_golden("plot_actions");
+}
+
+#[test]
+fn gcol() {
+    check_golden("gcol");
+}
+
+#[test]
+fn viewports() {
+    check_golden("viewports");
+}
+
+#[test]
+fn palette() {
+    check_golden("palette");
+}
+
+#[test]
+fn modes() {
+    check_golden("modes");
+}
+
+#[test]
+fn double_buffer() {
+    check_golden("double_buffer");
+}
+
+#[test]
+fn vdu5() {
+    check_golden("vdu5");
+}
+
+#[test]
+fn charset() {
+    check_golden("charset");
+}
+
+#[test]
+fn terminal_charset() {
+    check_golden("terminal_charset");
+}
+
+#[test]
+fn fonts() {
+    check_golden("fonts");
+}
+
+#[test]
+fn cursor() {
+    check_golden("cursor");
+}
+
+#[test]
+fn cursor_flags() {
+    check_golden("cursor_flags");
+}
@@ -0,0 +1,201 @@
+//! Replies of the VDP to the commands that read back the screen.
+
+mod common;
+
+use common::Host;
+
+impl Host<'_> {
+    // The last packet sent by the VDP, without its header.
+    fn reply(&mut self) -> Vec<u8> {
+        let bytes: Vec<u8> = self.rx.try_iter().collect();
+        let mut i = 0;
+        let mut packet = Vec::new();
+        while i + 1 < bytes.len() {
+            let len = bytes[i + 1] as usize;
+            packet = bytes[i + 2..i + 2 + len].to_vec();
+            i += 2 + len;
+        }
+        packet
+    }
+
+    fn screen_char(&mut self, x: u8, y: u8) -> u8 {
+        self.send(&[23, 0, 0x83, x, 0, y, 0]);
+        self.reply()[0]
+    }
+}
+
+#[test]
+fn screen_char() {
+    let mut host = Host::new();
+    host.send(&[22, 21, 17, 2, 17, 129]);
+    host.send(b"Az~");
+    host.send(&[27, 0x7F]);
+    host.send(&[23, 0xF0, 0x18, 0x3C, 0x7E, 0xFF, 0xFF, 0x7E, 0x3C, 0x18, 0xF0, b' ']);
+    assert_eq!(host.screen_char(0, 0), b'A');
+    assert_eq!(host.screen_char(1, 0), b'z');
+    assert_eq!(host.screen_char(2, 0), b'~');
+    assert_eq!(host.screen_char(3, 0), 0x7F);
+    assert_eq!(host.screen_char(4, 0), 0xF0);
+    assert_eq!(host.screen_char(5, 0), b' ');
+    assert_eq!(host.screen_char(100, 0), 0);
+}
+
+#[test]
+fn screen_char_follows_text() {
+    let mut host = Host::new();
+    host.send(&[22, 21]);
+    host.send(b"Top\r\nAB");
+    // Redefining a character does not change what was printed with it.
+    host.send(&[23, b'A', 0xFF, 0, 0xFF, 0, 0xFF, 0, 0xFF, 0]);
+    assert_eq!(host.screen_char(0, 1), b'A');
+    // Printing in the last line scrolls the text up.
+    host.send(&[31, 0, 47]);
+    host.send(b"Z\r\n");
+    assert_eq!(host.screen_char(0, 0), b'A');
+    assert_eq!(host.screen_char(1, 0), b'B');
+    assert_eq!(host.screen_char(0, 46), b'Z');
+    assert_eq!(host.screen_char(0, 47), b' ');
+    host.send(&[12]);
+    assert_eq!(host.screen_char(0, 0), b' ');
+}
+
+#[test]
+fn screen_pixel() {
+    let mut host = Host::new();
+    host.send(&[22, 21, 18, 0, 9, 25, 69, 0, 0, 0, 0]);
+    host.send(&[19, 9, 255, 0x12, 0x34, 0x56]);
+    host.send(&[23, 0, 0x84, 0, 0, 0, 0]);
+    assert_eq!(host.reply(), [0x12, 0x34, 0x56, 9]);
+}
+
+#[test]
+fn shapes_bigger_than_the_screen() {
+    let mut host = Host::new();
+    host.send(&[22, 18, 18, 0, 1, 19, 1, 255, 0x12, 0x34, 0x56]);
+    host.send(&[25, 4, 0x00, 0x80, 0, 0, 25, 157, 0xFF, 0x7F, 0, 0]);
+    host.send(&[23, 0, 0x84, 0, 0, 0, 0]);
+    assert_eq!(host.reply(), [0x12, 0x34, 0x56, 1]);
+    host.send(&[16, 25, 4, 0, 0, 0, 0, 25, 4, 0xFF, 0x7F, 0, 0, 25, 205, 0, 0, 0xFF, 0x7F]);
+    host.send(&[23, 0, 0x84, 0, 2, 0, 2, 0]);
+    assert_eq!(host.reply(), [0x12, 0x34, 0x56, 1]);
+    host.send(&[25, 4, 0, 0, 0, 0, 25, 4, 0xFF, 0x7F, 0, 0, 25, 197, 0, 0, 0xFF, 0x7F]);
+}
+
+// The pixels of row y of the character cell at 0,0, with the
+// coordinates in pixels.
+fn cell_row(host: &mut Host, y: u8) -> Vec<u8> {
+    (0..8).map(|x| {
+        host.send(&[23, 0, 0x84, x, 0, y, 0]);
+        host.reply()[3]
+    }).collect()
+}
+
+#[test]
+fn redefined_characters_stay_with_their_font() {
+    let mut host = Host::new();
+    host.send(&[22, 21, 23, 0, 0xC0, 0]);
+    host.send(&[23, b'A', 0x80, 0, 0, 0, 0, 0, 0, 0x01]);
+    // An 8x16 font loaded from a buffer.
+    host.send(&[23, 0, 0xA0, 1, 0, 0, 0x00, 0x10]);
+    host.send(&[0; 0x1000]);
+    host.send(&[23, 0, 0x95, 1, 1, 0, 8, 16, 0, 0, 23, 0, 0x95, 0, 1, 0, 0]);
+    host.send(&[23, b'B', 0xFF, 0, 0, 0, 0, 0, 0, 0xFF]);
+    // Select the system font and back again.
+    host.send(&[23, 0, 0x95, 0, 0xFF, 0xFF, 0, 23, 0, 0x95, 0, 1, 0, 0]);
+    host.send(b"B");
+    // The 8 rows are scaled to the height of the font.
+    for y in [0, 1, 14, 15] {
+        assert_eq!(cell_row(&mut host, y), [15; 8], "row {}", y);
+    }
+    assert_eq!(cell_row(&mut host, 2), [0; 8]);
+    host.send(&[23, 0, 0x95, 0, 0xFF, 0xFF, 0, 12, b'A']);
+    assert_eq!(cell_row(&mut host, 0), [15, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(cell_row(&mut host, 7), [0, 0, 0, 0, 0, 0, 0, 15]);
+}
+
+// The characters of text row y from column 0.
+fn text_row(host: &Host, y: i32, n: i32) -> String {
+    (0..n).map(|x| host.vdp.screen_char(x, y) as char).collect()
+}
+
+#[test]
+fn screen_char_after_clg() {
+    let mut host = Host::new();
+    host.send(&[22, 21]);
+    host.send(b"Hello");
+    host.send(&[16]);
+    assert_eq!(host.screen_char(0, 0), b' ');
+    // Selecting a font redraws the text, there is nothing left to draw.
+    host.send(&[23, 0, 0x95, 0, 0xFF, 0xFF, 0]);
+    assert_eq!(host.screen_char(0, 0), b' ');
+    assert_eq!(cell_row(&mut host, 1), [0; 8]);
+}
+
+#[test]
+fn screen_char_after_graphics() {
+    let mut host = Host::new();
+    host.send(&[22, 21, 23, 0, 0xC0, 0]);
+    host.send(b"Hello\r\nWorld\r\nAgain");
+    // A point in the H, a line through the W.
+    host.send(&[25, 69, 1, 0, 1, 0]);
+    host.send(&[25, 4, 0, 0, 10, 0, 25, 5, 15, 0, 10, 0]);
+    assert_eq!(text_row(&host, 0, 5), " ello");
+    assert_eq!(text_row(&host, 1, 5), "  rld");
+    // A copied block is no text where it ends up, a moved one leaves
+    // the background behind.
+    host.send(&[25, 4, 0, 0, 16, 0, 25, 4, 15, 0, 23, 0, 25, 190, 24, 0, 23, 0]);
+    assert_eq!(text_row(&host, 2, 8), "Aga     ");
+    host.send(&[25, 4, 0, 0, 16, 0, 25, 4, 15, 0, 23, 0, 25, 189, 48, 0, 23, 0]);
+    assert_eq!(text_row(&host, 2, 8), "  a     ");
+}
+
+#[test]
+fn screen_char_after_vdu5() {
+    let mut host = Host::new();
+    host.send(&[22, 21, 23, 0, 0xC0, 0]);
+    host.send(b"Hello");
+    host.send(&[5, 25, 4, 16, 0, 0, 0]);
+    host.send(b"x");
+    host.send(&[4]);
+    assert_eq!(text_row(&host, 0, 5), "He lo");
+}
+
+// Switch to terminal mode and print a line of digits on each row.
+fn terminal() -> Host<'static> {
+    let mut host = Host::new();
+    host.send(&[23, 0, 0xFF]);
+    for row in 0..5 {
+        host.send(format!("\x1b[{};1H{}{}{}{}", row + 1, row, row, row, row).as_bytes());
+    }
+    host
+}
+
+fn terminal_rows(host: &Host) -> Vec<String> {
+    (0..5).map(|y| text_row(host, y, 4)).collect()
+}
+
+#[test]
+fn screen_char_after_terminal_clear() {
+    let mut host = terminal();
+    host.send(b"\x1b[2;3H\x1b[K");
+    assert_eq!(terminal_rows(&host), ["0000", "11  ", "2222", "3333", "4444"]);
+    host.send(b"\x1b[1K");
+    assert_eq!(terminal_rows(&host), ["0000", "    ", "2222", "3333", "4444"]);
+    host.send(b"\x1b[3;2H\x1b[J");
+    assert_eq!(terminal_rows(&host), ["0000", "    ", "2   ", "    ", "    "]);
+    host.send(b"\x1b[1J");
+    assert_eq!(terminal_rows(&host), ["    "; 5]);
+
+    let mut host = terminal();
+    host.send(b"\x1b[2J");
+    assert_eq!(terminal_rows(&host), ["    "; 5]);
+}
+
+#[test]
+fn screen_char_after_terminal_line_changes() {
+    let mut host = terminal();
+    host.send(b"\x1b[2;1H\x1b[2M");
+    assert_eq!(terminal_rows(&host), ["0000", "3333", "4444", "    ", "    "]);
+    host.send(b"\x1b[L");
+    assert_eq!(terminal_rows(&host), ["0000", "    ", "3333", "4444", "    "]);
+}
@@ -0,0 +1,99 @@
+//! Saving and restoring the VDP state with `snapshot` and `restore`.
+
+mod common;
+
+use common::Host;
+
+impl Host<'_> {
+    fn frame(&mut self) -> Vec<(u8, u8, u8)> {
+        self.vdp.frame().pixels().iter().map(|c| (c.r, c.g, c.b)).collect()
+    }
+}
+
+// Some of everything: a mode, palette change, text window, colours,
+// graphics, a redefined character and a sprite.
+const SETUP: &[u8] = &[
+    22, 8, 19, 1, 255, 0x10, 0x20, 0x30, 28, 2, 20, 30, 2, 17, 3, 17, 129, b'H', b'i',
+    18, 0, 2, 25, 4, 100, 0, 100, 0, 25, 0x55, 200, 0, 0, 0,
+    23, b'!', 0xFF, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0xFF, b'!',
+    23, 27, 0, 1, 23, 27, 1, 2, 0, 2, 0, 0xFF, 0, 0, 0xFF, 0, 0xFF, 0, 0xFF, 0xFF, 0, 0, 0xFF, 0xFF, 0, 0, 0xFF,
+    23, 27, 3, 8, 0, 8, 0, 23, 27, 4, 1, 23, 27, 6, 1, 23, 27, 7, 2, 23, 27, 11, 23, 27, 15,
+];
+
+const MORE: &[u8] = &[b'!', 10, 13, b'x', 25, 5, 0, 0, 0, 0, 23, 27, 13, 20, 0, 20, 0];
+
+#[test]
+fn restore_continues_where_snapshot_was_taken() {
+    let mut original = Host::new();
+    original.send(SETUP);
+    let snapshot = original.vdp.snapshot();
+
+    let mut copy = Host::new();
+    copy.vdp.restore(&snapshot).unwrap();
+    assert!(copy.frame() == original.frame(), "restored frame differs");
+    assert_eq!(copy.vdp.snapshot(), snapshot);
+
+    original.send(MORE);
+    copy.send(MORE);
+    assert!(copy.frame() == original.frame(), "frames differ after more commands");
+}
+
+#[test]
+fn bad_snapshots_are_rejected() {
+    let mut host = Host::new();
+    host.send(SETUP);
+    let snapshot = host.vdp.snapshot();
+
+    let mut fresh = Host::new();
+    let before = fresh.vdp.snapshot();
+    assert!(fresh.vdp.restore(b"not a snapshot").is_err());
+    assert!(fresh.vdp.restore(&snapshot[..snapshot.len() - 1]).is_err());
+    let mut future = snapshot.clone();
+    future[4] = 99;
+    assert!(fresh.vdp.restore(&future).is_err());
+    assert_eq!(fresh.vdp.snapshot(), before);
+}
+
+#[test]
+fn out_of_range_values_are_rejected() {
+    let mut host = Host::new();
+    host.send(SETUP);
+    let snapshot = host.vdp.snapshot();
+    let frame = host.vdp.frame();
+    let screen_size = (frame.width() * frame.height()) as usize;
+    let pixels = 8 + 3 * snapshot[7] as usize;
+    let colours = pixels + 2 * screen_size + 1;
+    // Sprite 1 has bitmap 1 as its only frame, sprites 2 to 254 have none.
+    let sprite = snapshot.len() - 253 * 10 - 11;
+    assert_eq!(snapshot[sprite..sprite + 6], [1, 0, 0, 0, 1, 0]);
+
+    let mut fresh = Host::new();
+    let before = fresh.vdp.snapshot();
+    for (offset, value) in [(pixels, 200), (pixels + screen_size, 200), (colours, 200), (colours + 3, 255), (colours + 14, 0x7F), (colours + 18, 0x80), (sprite + 4, 5), (sprite + 5, 1)] {
+        let mut bad = snapshot.clone();
+        bad[offset] = value;
+        assert!(fresh.vdp.restore(&bad).is_err(), "{} at offset {}", value, offset);
+    }
+    assert_eq!(fresh.vdp.snapshot(), before);
+    fresh.vdp.frame();
+}
+
+#[test]
+fn bad_text_colours_are_rejected() {
+    let mut host = Host::new();
+    host.send(SETUP);
+    let snapshot = host.vdp.snapshot();
+    // The cells of "Hi": set, character, foreground, background, underline.
+    let cell = snapshot.windows(7).position(|w| w[..2] == [1, b'H'] && w[5..] == [1, b'i']).unwrap();
+
+    let mut fresh = Host::new();
+    let before = fresh.vdp.snapshot();
+    for offset in [cell + 2, cell + 3] {
+        let mut bad = snapshot.clone();
+        bad[offset] = 200;
+        assert!(fresh.vdp.restore(&bad).is_err(), "colour at offset {}", offset);
+    }
+    assert_eq!(fresh.vdp.snapshot(), before);
+    fresh.send(&[23, 0, 0x95, 0, 0xFF, 0xFF, 0]);
+    fresh.vdp.frame();
+}
@@ -0,0 +1,35 @@
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::time::Duration;
+
+use agon_light_vdp::transport::{Link, Transport};
+
+fn round_trip(link: &Link, host: &mut (impl Read + Write)) {
+    host.write_all(&[0x16, 0x01]).unwrap();
+    assert_eq!(link.rx.recv_timeout(Duration::from_secs(5)).unwrap(), 0x16);
+    assert_eq!(link.rx.recv_timeout(Duration::from_secs(5)).unwrap(), 0x01);
+
+    for b in [0x86, 0x02, 0x00, 0x00] {
+        link.tx.send(b).unwrap();
+    }
+    let mut packet = [0u8; 4];
+    host.read_exact(&mut packet).unwrap();
+    assert_eq!(packet, [0x86, 0x02, 0x00, 0x00]);
+}
+
+#[test]
+fn tcp_connect() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let link = Link::open(&Transport::TcpConnect(address)).unwrap();
+    let (mut host, _) = listener.accept().unwrap();
+    round_trip(&link, &mut host);
+}
+
+#[cfg(unix)]
+#[test]
+fn pty() {
+    let link = Link::open(&Transport::Pty).unwrap();
+    let mut host = std::fs::File::options().read(true).write(true).open(&link.name).unwrap();
+    round_trip(&link, &mut host);
+}
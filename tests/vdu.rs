@@ -0,0 +1,15 @@
+//! Splitting the byte stream from the eZ80 into VDU commands.
+
+mod common;
+
+use common::Host;
+
+#[test]
+fn largest_bitmap_waits_for_its_data() {
+    let mut host = Host::new();
+    host.send(&[22, 21, 23, 27, 0, 1]);
+    host.send(&[23, 27, 1, 0xFF, 0x7F, 0xFF, 0x7F]);
+    // Everything that follows is taken as pixels of the bitmap.
+    host.send(b"Text");
+    assert_eq!(host.vdp.screen_char(0, 0), b' ');
+}